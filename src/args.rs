@@ -1,9 +1,52 @@
+use crate::ast::Strategy;
 use clap::Parser;
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
     #[arg()]
     pub file: Option<String>,
+    /// Print the lowered program in another language instead of running it
+    #[arg(long, value_enum, requires = "file")]
+    pub emit: Option<Emit>,
+    /// Simplify the lowered program before running or emitting it
+    #[arg(long, requires = "file")]
+    pub optimize: bool,
+    /// Print the lowered program instead of running it
+    #[arg(long, requires = "file")]
+    pub dump_ir: bool,
+    /// How arguments are passed when running the program
+    #[arg(long, value_enum, default_value_t)]
+    pub strategy: Strategy,
+    /// Print a resulting function as its normal form
+    #[arg(long)]
+    pub normalize: bool,
+    /// Stop evaluating or tracing after this many steps
+    #[arg(long)]
+    pub fuel: Option<u64>,
+    /// Stop evaluating or tracing after this many seconds
+    #[arg(long)]
+    pub timeout: Option<f64>,
+    /// Stop evaluating or tracing when calls are nested this deep
+    #[arg(long, default_value_t = 100_000)]
+    pub max_depth: usize,
+    /// Print every reduction step instead of just the result
+    #[arg(long, requires = "file")]
+    pub trace: bool,
+    /// Only trace steps inside at most this many nested proc calls
+    #[arg(long, requires = "trace")]
+    pub trace_depth: Option<usize>,
+    /// Don't trace arithmetic steps
+    #[arg(long, requires = "trace")]
+    pub trace_skip_arithmetic: bool,
+    /// Only trace steps inside calls to this proc
+    #[arg(long, requires = "trace")]
+    pub trace_proc: Option<String>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum Emit {
+    Wat,
+    Asm,
 }
 pub fn get_args() -> Args {
     Args::parse()
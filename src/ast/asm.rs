@@ -0,0 +1,1209 @@
+//! Emits x86-64 GNU assembler text from the lowered program.
+//! Procs follow the System V calling convention and every value is a 64 bit word:
+//! numbers are sign extended i32s wrapping around on overflow, bools are 0 or 1 and functions
+//! are pointers to closure records `[code, captures...]` allocated by a bump allocator, tuples
+//! are pointers to records of their elements and records are laid out like tuples, with the
+//! fields in the order they're declared.
+//! Chars are their codes, strings and lists are pointers to records `[length, codes...]`
+//! and `[length, elements...]`, indexing past the end aborts.
+//! A closure's code is called with the record in %rdi and the argument in %rsi.
+//! The captures of a record are sorted by their scope.
+//! Values don't know their types, so the result of main is printed by a routine made for what
+//! it's inferred to be, printing it like the evaluator does. Programs whose result or operands
+//! can't be checked that way aren't compiled
+use super::FinalLevel;
+use super::level0::GlobalSymbol;
+use super::level1::{BinaryOpKind, Binding, Builtin, Expr, Pattern, Top};
+use crate::common::{Ident, Scope};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+const ARG_REGISTERS: [&str; 6] = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+const HEAP_SIZE: usize = 1 << 26;
+
+const RUNTIME: &str = r#"    .text
+rt_alloc:
+    movq rt_heap_ptr(%rip), %rax
+    addq %rax, %rdi
+    leaq rt_heap_end(%rip), %rcx
+    cmpq %rcx, %rdi
+    ja rt_out_of_memory
+    movq %rdi, rt_heap_ptr(%rip)
+    ret
+rt_concat:
+    movq %rdi, %r8
+    movq %rsi, %r9
+    movq (%r8), %rdx
+    addq (%r9), %rdx
+    leaq 8(,%rdx,8), %rdi
+    call rt_alloc
+    movq %rdx, (%rax)
+    leaq 8(%rax), %rdi
+    leaq 8(%r8), %rsi
+    movq (%r8), %rcx
+    rep movsq
+    leaq 8(%r9), %rsi
+    movq (%r9), %rcx
+    rep movsq
+    ret
+rt_index:
+    cmpq (%rdi), %rsi
+    jae rt_out_of_bounds
+    movq 8(%rdi,%rsi,8), %rax
+    ret
+rt_map:
+    pushq %rbx
+    pushq %r12
+    pushq %r13
+    pushq %r14
+    pushq %r15
+    movq %rdi, %r12
+    movq %rsi, %r13
+    movq (%r12), %rdi
+    leaq 8(,%rdi,8), %rdi
+    call rt_alloc
+    movq %rax, %r14
+    movq (%r12), %rcx
+    movq %rcx, (%r14)
+    xorl %ebx, %ebx
+rt_map_loop:
+    cmpq (%r12), %rbx
+    jae rt_map_end
+    movq %r13, %rdi
+    movq 8(%r12,%rbx,8), %rsi
+    call *(%rdi)
+    movq %rax, 8(%r14,%rbx,8)
+    incq %rbx
+    jmp rt_map_loop
+rt_map_end:
+    movq %r14, %rax
+    popq %r15
+    popq %r14
+    popq %r13
+    popq %r12
+    popq %rbx
+    ret
+rt_fold:
+    pushq %rbx
+    pushq %r12
+    pushq %r13
+    pushq %r14
+    pushq %r15
+    movq %rdi, %r12
+    movq %rsi, %r14
+    movq %rdx, %r13
+    xorl %ebx, %ebx
+rt_fold_loop:
+    cmpq (%r12), %rbx
+    jae rt_fold_end
+    movq %r13, %rdi
+    movq %r14, %rsi
+    call *(%rdi)
+    movq %rax, %rdi
+    movq 8(%r12,%rbx,8), %rsi
+    call *(%rdi)
+    movq %rax, %r14
+    incq %rbx
+    jmp rt_fold_loop
+rt_fold_end:
+    movq %r14, %rax
+    popq %r15
+    popq %r14
+    popq %r13
+    popq %r12
+    popq %rbx
+    ret
+rt_push:
+    movq %rdi, %r8
+    movq %rsi, %r9
+    movq (%r8), %rdx
+    leaq 16(,%rdx,8), %rdi
+    call rt_alloc
+    leaq 1(%rdx), %rcx
+    movq %rcx, (%rax)
+    leaq 8(%rax), %rdi
+    leaq 8(%r8), %rsi
+    movq %rdx, %rcx
+    rep movsq
+    movq %r9, (%rdi)
+    ret
+rt_out_of_bounds:
+    call abort@PLT
+rt_out_of_memory:
+    call abort@PLT
+rt_division_by_zero:
+    call abort@PLT
+rt_no_match:
+    call abort@PLT
+rt_print_number:
+    subq $8, %rsp
+    movl %edi, %esi
+    leaq rt_number_format(%rip), %rdi
+    xorl %eax, %eax
+    call printf@PLT
+    addq $8, %rsp
+    ret
+rt_print_text:
+    subq $8, %rsp
+    movq %rdi, %rsi
+    leaq rt_text_format(%rip), %rdi
+    xorl %eax, %eax
+    call printf@PLT
+    addq $8, %rsp
+    ret
+rt_print_separator:
+    leaq rt_separator(%rip), %rdi
+    jmp rt_print_text
+rt_print_bool:
+    leaq rt_true(%rip), %rax
+    leaq rt_false(%rip), %rcx
+    testq %rdi, %rdi
+    cmovzq %rcx, %rax
+    movq %rax, %rdi
+    jmp rt_print_text
+rt_put_char:
+    pushq %rbx
+    movl %edi, %ebx
+    cmpl $0x80, %ebx
+    jae rt_put_char_2
+    call putchar@PLT
+    popq %rbx
+    ret
+rt_put_char_2:
+    cmpl $0x800, %ebx
+    jae rt_put_char_3
+    shrl $6, %edi
+    orl $0xc0, %edi
+    call putchar@PLT
+    jmp rt_put_char_last
+rt_put_char_3:
+    cmpl $0x10000, %ebx
+    jae rt_put_char_4
+    shrl $12, %edi
+    orl $0xe0, %edi
+    call putchar@PLT
+    jmp rt_put_char_second_last
+rt_put_char_4:
+    shrl $18, %edi
+    orl $0xf0, %edi
+    call putchar@PLT
+    movl %ebx, %edi
+    shrl $12, %edi
+    andl $0x3f, %edi
+    orl $0x80, %edi
+    call putchar@PLT
+rt_put_char_second_last:
+    movl %ebx, %edi
+    shrl $6, %edi
+    andl $0x3f, %edi
+    orl $0x80, %edi
+    call putchar@PLT
+rt_put_char_last:
+    movl %ebx, %edi
+    andl $0x3f, %edi
+    orl $0x80, %edi
+    call putchar@PLT
+    popq %rbx
+    ret
+rt_put_escaped:
+    pushq %rbx
+    pushq %r12
+    subq $8, %rsp
+    movl %edi, %ebx
+    movl %edi, %r12d
+    cmpl %esi, %edi
+    je rt_put_escaped_letter
+    cmpl $92, %edi
+    je rt_put_escaped_letter
+    movl $110, %r12d
+    cmpl $10, %edi
+    je rt_put_escaped_letter
+    movl $116, %r12d
+    cmpl $9, %edi
+    je rt_put_escaped_letter
+    movl $114, %r12d
+    cmpl $13, %edi
+    je rt_put_escaped_letter
+    movl $48, %r12d
+    testl %edi, %edi
+    je rt_put_escaped_letter
+    cmpl $32, %edi
+    jb rt_put_escaped_code
+    cmpl $127, %edi
+    jb rt_put_escaped_plain
+    cmpl $159, %edi
+    jbe rt_put_escaped_code
+rt_put_escaped_plain:
+    call rt_put_char
+    jmp rt_put_escaped_end
+rt_put_escaped_letter:
+    movl $92, %edi
+    call putchar@PLT
+    movl %r12d, %edi
+    call putchar@PLT
+    jmp rt_put_escaped_end
+rt_put_escaped_code:
+    movl %ebx, %esi
+    leaq rt_code_format(%rip), %rdi
+    xorl %eax, %eax
+    call printf@PLT
+rt_put_escaped_end:
+    addq $8, %rsp
+    popq %r12
+    popq %rbx
+    ret
+rt_print_char:
+    pushq %rbx
+    movl %edi, %ebx
+    movl $39, %edi
+    call putchar@PLT
+    movl %ebx, %edi
+    movl $39, %esi
+    call rt_put_escaped
+    movl $39, %edi
+    call putchar@PLT
+    popq %rbx
+    ret
+rt_print_str:
+    pushq %rbx
+    pushq %r12
+    subq $8, %rsp
+    movq %rdi, %r12
+    movl $34, %edi
+    call putchar@PLT
+    xorl %ebx, %ebx
+rt_print_str_loop:
+    cmpq (%r12), %rbx
+    jae rt_print_str_end
+    movq 8(%r12,%rbx,8), %rdi
+    movl $34, %esi
+    call rt_put_escaped
+    incq %rbx
+    jmp rt_print_str_loop
+rt_print_str_end:
+    movl $34, %edi
+    call putchar@PLT
+    addq $8, %rsp
+    popq %r12
+    popq %rbx
+    ret
+"#;
+
+const RODATA: &str = r#"
+    .section .rodata
+rt_number_format:
+    .string "%d"
+rt_text_format:
+    .string "%s"
+rt_code_format:
+    .string "\\u{%x}"
+rt_separator:
+    .string ", "
+rt_true:
+    .string "true"
+rt_false:
+    .string "false"
+"#;
+
+/// The label of a proc. The procs of operators are named by their symbols,
+/// which labels can't have, so those are written as their codes
+fn proc_label(name: GlobalSymbol) -> String {
+    let mut label = "tl_".to_string();
+    for c in name.0.as_str().chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            label.push(c);
+        } else {
+            label += &format!(".{:x}", u32::from(c));
+        }
+    }
+    label
+}
+
+/// What a value is, as far as printing it goes
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Shape {
+    Number,
+    Bool,
+    Char,
+    Str,
+    Tuple(Vec<Self>),
+    List(Box<Self>),
+    Record(Vec<(Ident, Self)>),
+    Function(BTreeSet<Scope>, Box<Self>), // the arguments of the lambdas it can be, what it returns
+    Nothing,                              // no value, like the elements of an empty list
+    Unknown,                              // any value
+}
+
+impl Shape {
+    /// What a value is when it can be either, which is unknown if they disagree.
+    /// The arguments of the functions lost that way are put in `escaped`
+    fn merge(self, other: Self, escaped: &mut Vec<Scope>) -> Self {
+        match (self, other) {
+            (Self::Nothing, shape) | (shape, Self::Nothing) => shape,
+            (Self::Tuple(a), Self::Tuple(b)) if a.len() == b.len() => Self::Tuple(
+                a.into_iter()
+                    .zip(b)
+                    .map(|(a, b)| a.merge(b, escaped))
+                    .collect(),
+            ),
+            (Self::List(a), Self::List(b)) => Self::List(Box::new(a.merge(*b, escaped))),
+            (Self::Record(a), Self::Record(b))
+                if a.iter().map(|(f, _)| f).eq(b.iter().map(|(f, _)| f)) =>
+            {
+                let fields = a.into_iter().zip(b);
+                let fields = fields.map(|((f, a), (_, b))| (f, a.merge(b, escaped)));
+                Self::Record(fields.collect())
+            }
+            (Self::Function(mut a, x), Self::Function(b, y)) => {
+                a.extend(b);
+                Self::Function(a, Box::new(x.merge(*y, escaped)))
+            }
+            (a, b) if a == b => a,
+            (a, b) => {
+                a.functions(escaped);
+                b.functions(escaped);
+                Self::Unknown
+            }
+        }
+    }
+
+    /// The arguments of the functions in a value of the shape
+    fn functions(&self, args: &mut Vec<Scope>) {
+        match self {
+            Self::Tuple(shapes) => shapes.iter().for_each(|shape| shape.functions(args)),
+            Self::List(shape) => shape.functions(args),
+            Self::Record(fields) => fields.iter().for_each(|(_, shape)| shape.functions(args)),
+            Self::Function(scopes, result) => {
+                args.extend(scopes);
+                result.functions(args);
+            }
+            _ => {}
+        }
+    }
+
+    fn element(self) -> Self {
+        match self {
+            Self::List(element) => *element,
+            Self::Nothing => Self::Nothing,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Whether the operation surely works on values of the shapes once compiled,
+    /// which only know the words they're made of
+    fn can_apply(lhs: &Self, kind: BinaryOpKind, rhs: &Self) -> bool {
+        match kind {
+            BinaryOpKind::Call => true,
+            // lists are concatenated like strings
+            BinaryOpKind::Concatenation => [lhs, rhs]
+                .iter()
+                .all(|shape| matches!(shape, Self::Str | Self::List(_) | Self::Nothing)),
+            BinaryOpKind::Addition
+            | BinaryOpKind::Subtraction
+            | BinaryOpKind::Multiplication
+            | BinaryOpKind::Division
+            | BinaryOpKind::Remainder => [lhs, rhs]
+                .iter()
+                .all(|shape| matches!(shape, Self::Number | Self::Nothing)),
+            BinaryOpKind::Equal
+            | BinaryOpKind::NotEqual
+            | BinaryOpKind::Less
+            | BinaryOpKind::LessEqual
+            | BinaryOpKind::Greater
+            | BinaryOpKind::GreaterEqual => match lhs.clone().merge(rhs.clone(), &mut vec![]) {
+                Self::Number | Self::Char | Self::Nothing => true,
+                Self::Bool => matches!(kind, BinaryOpKind::Equal | BinaryOpKind::NotEqual),
+                // strings and the others would be compared by their addresses
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Infers the shapes of expressions from the literals they're made of, following the calls
+/// from main with the shapes of their arguments, as the declared types aren't checked.
+/// The arguments of lambdas get the shapes of everything they're called with,
+/// so it's inferred again until they stop growing
+#[derive(Debug, Default)]
+struct Shapes<'t> {
+    procs: HashMap<GlobalSymbol, &'t Top<'t>>,
+    calls: HashMap<(GlobalSymbol, Vec<Shape>), Shape>, // what they return
+    bindings: HashMap<Binding, Shape>,
+    grown: bool,
+    checking: bool, // whether operands are checked, once the shapes are final
+}
+
+impl<'t> Shapes<'t> {
+    /// How many times the program is inferred again before giving up
+    const ROUNDS: usize = 32;
+
+    fn new(tops: &[&'t Top<'t>]) -> Self {
+        let procs = tops
+            .iter()
+            .map(|top| match top {
+                Top::Procedure { name, .. } => (*name, *top),
+            })
+            .collect();
+        Self {
+            procs,
+            ..Self::default()
+        }
+    }
+
+    /// What main returns, unknown if the shapes don't settle
+    fn main(mut self) -> Shape {
+        let main = GlobalSymbol(Ident::new("main"));
+        for _ in 0..Self::ROUNDS {
+            self.grown = false;
+            self.calls.clear();
+            let shape = self.call(main, vec![]);
+            if !self.grown {
+                self.checking = true;
+                self.calls.clear();
+                self.call(main, vec![]);
+                return shape;
+            }
+        }
+        Shape::Unknown
+    }
+
+    fn merge(&mut self, a: Shape, b: Shape) -> Shape {
+        let mut escaped = vec![];
+        let shape = a.merge(b, &mut escaped);
+        for scope in escaped {
+            self.grow(Binding { scope }, Shape::Unknown);
+        }
+        shape
+    }
+
+    /// Lets the binding also be of the shape
+    fn grow(&mut self, binding: Binding, shape: Shape) {
+        let old = self.bindings.remove(&binding).unwrap_or(Shape::Nothing);
+        let new = self.merge(old.clone(), shape);
+        self.grown |= old != new;
+        self.bindings.insert(binding, new);
+    }
+
+    /// What calling a function of the shape with the argument returns
+    fn apply(&mut self, function: Shape, arg: Shape) -> Shape {
+        match function {
+            Shape::Function(scopes, result) => {
+                for scope in scopes {
+                    self.grow(Binding { scope }, arg.clone());
+                }
+                *result
+            }
+            Shape::Nothing => Shape::Nothing,
+            _ => {
+                // it's called by something unknown
+                let mut escaped = vec![];
+                arg.functions(&mut escaped);
+                for scope in escaped {
+                    self.grow(Binding { scope }, Shape::Unknown);
+                }
+                Shape::Unknown
+            }
+        }
+    }
+
+    /// What the proc returns when it's called with arguments of the shapes
+    fn call(&mut self, name: GlobalSymbol, shapes: Vec<Shape>) -> Shape {
+        let key = (name, shapes);
+        if let Some(shape) = self.calls.get(&key) {
+            return shape.clone();
+        }
+        let Some(Top::Procedure { args, body, .. }) = self.procs.get(&name).copied() else {
+            return Shape::Unknown;
+        };
+        // procs only call the ones before them, so this doesn't recurse forever
+        for ((binding, _), shape) in args.iter().zip(&key.1) {
+            self.bindings.insert(*binding, shape.clone());
+        }
+        let shape = self.expr(body);
+        self.calls.insert(key, shape.clone());
+        shape
+    }
+
+    fn pattern(&mut self, pattern: &Pattern, shape: Shape) {
+        match (pattern, shape) {
+            (Pattern::Binding(binding), shape) => {
+                self.bindings.insert(*binding, shape);
+            }
+            (Pattern::Tuple(patterns), Shape::Tuple(shapes)) if patterns.len() == shapes.len() => {
+                for (pattern, shape) in patterns.iter().zip(shapes) {
+                    self.pattern(pattern, shape);
+                }
+            }
+            (Pattern::Tuple(patterns), _) => {
+                for pattern in patterns {
+                    self.pattern(pattern, Shape::Unknown);
+                }
+            }
+            (Pattern::Wildcard | Pattern::Number(_) | Pattern::Bool(_), _) => {}
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Shape {
+        match expr {
+            Expr::Number(_) => Shape::Number,
+            Expr::Bool(_) => Shape::Bool,
+            Expr::Str(_) => Shape::Str,
+            Expr::Char(_) => Shape::Char,
+            Expr::Tuple(elements) => Shape::Tuple(elements.iter().map(|e| self.expr(e)).collect()),
+            Expr::List(elements) => {
+                let mut element = Shape::Nothing;
+                for e in elements {
+                    let shape = self.expr(e);
+                    element = self.merge(element, shape);
+                }
+                Shape::List(Box::new(element))
+            }
+            Expr::Record(fields) => Shape::Record(
+                fields
+                    .iter()
+                    .map(|(field, value)| (*field, self.expr(value)))
+                    .collect(),
+            ),
+            Expr::Field { record, field, idx } => match self.expr(record) {
+                Shape::Record(mut fields)
+                    if fields.get(*idx).is_some_and(|(name, _)| name == field) =>
+                {
+                    fields.swap_remove(*idx).1
+                }
+                _ => Shape::Unknown,
+            },
+            Expr::LambdaFunction { arg, body, .. } => {
+                self.bindings.entry(*arg).or_insert(Shape::Nothing);
+                let result = self.expr(body);
+                Shape::Function(BTreeSet::from([arg.scope]), Box::new(result))
+            }
+            Expr::LetRec { bindings, body } => {
+                // what they refer to is from the round before
+                for (binding, value) in bindings {
+                    let shape = self.expr(value);
+                    self.grow(*binding, shape);
+                }
+                self.expr(body)
+            }
+            Expr::BinaryOperation(lhs, kind, rhs) => {
+                let (lhs, rhs) = (*lhs, *rhs);
+                if *kind == BinaryOpKind::Call {
+                    let arg = self.expr(rhs);
+                    // a let binding
+                    if let Expr::LambdaFunction {
+                        arg: binding, body, ..
+                    } = lhs
+                    {
+                        self.bindings.insert(*binding, arg);
+                        return self.expr(body);
+                    }
+                    let function = self.expr(lhs);
+                    return self.apply(function, arg);
+                }
+                let (lhs, rhs) = (self.expr(lhs), self.expr(rhs));
+                if self.checking && !Shape::can_apply(&lhs, *kind, &rhs) {
+                    let operator = kind.to_string();
+                    panic!(
+                        "`{}` is applied to values it doesn't work on, so it can't be compiled",
+                        operator.trim()
+                    ) // TODO: report it properly
+                }
+                match kind {
+                    BinaryOpKind::Concatenation => self.merge(lhs, rhs),
+                    BinaryOpKind::Addition
+                    | BinaryOpKind::Subtraction
+                    | BinaryOpKind::Multiplication
+                    | BinaryOpKind::Division
+                    | BinaryOpKind::Remainder => Shape::Number,
+                    _ => Shape::Bool,
+                }
+            }
+            Expr::Referal { scope } => self
+                .bindings
+                .get(&Binding { scope: *scope })
+                .cloned()
+                .unwrap_or(Shape::Nothing),
+            Expr::ProcCall { name, args } => {
+                let shapes = args.iter().map(|arg| self.expr(arg)).collect();
+                self.call(*name, shapes)
+            }
+            Expr::Builtin { builtin, args } => {
+                let mut args = args.iter().map(|arg| self.expr(arg)).collect::<Vec<_>>();
+                match builtin {
+                    Builtin::Length => Shape::Number,
+                    Builtin::Index => args.swap_remove(0).element(),
+                    Builtin::Map => {
+                        let function = args.swap_remove(1);
+                        let element = args.swap_remove(0).element();
+                        Shape::List(Box::new(self.apply(function, element)))
+                    }
+                    Builtin::Fold => {
+                        let function = args.swap_remove(2);
+                        let init = args.swap_remove(1);
+                        let element = args.swap_remove(0).element();
+                        // the accumulator is the initial value or what the function returns
+                        let step = self.apply(function.clone(), init.clone());
+                        let result = self.apply(step, element.clone());
+                        let acc = self.merge(init, result);
+                        let step = self.apply(function, acc.clone());
+                        let result = self.apply(step, element);
+                        self.merge(acc, result)
+                    }
+                    Builtin::Push => {
+                        let element = args.swap_remove(1);
+                        let list = args.swap_remove(0).element();
+                        Shape::List(Box::new(self.merge(list, element)))
+                    }
+                }
+            }
+            Expr::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.expr(condition);
+                let then = self.expr(then);
+                let otherwise = self.expr(otherwise);
+                self.merge(then, otherwise)
+            }
+            Expr::Destructure {
+                value,
+                pattern,
+                body,
+            } => {
+                let value = self.expr(value);
+                self.pattern(pattern, value);
+                self.expr(body)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Module {
+    lambdas: Vec<String>,
+    labels: usize,                    // the local labels used so far
+    printers: HashMap<Shape, String>, // the labels of the routines printing compound values
+    routines: Vec<String>,
+    texts: Vec<String>, // the names of fields, printed before their values
+}
+
+struct Function<'m> {
+    module: &'m mut Module,
+    env: Vec<Binding>, // the captures of the closure record, stored in the first slot
+    locals: Vec<Binding>, // the arguments, recursive and destructured bindings,
+    // stored in the slots after the record
+    depth: usize, // words pushed on top of the frame
+    code: Vec<String>,
+}
+
+impl Module {
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!(".L{}", self.labels - 1)
+    }
+
+    /// The routine printing a value of the shape, which is given in %rdi
+    fn printer(&mut self, shape: &Shape) -> String {
+        match shape {
+            Shape::Number => return "rt_print_number".to_string(),
+            Shape::Bool => return "rt_print_bool".to_string(),
+            Shape::Char => return "rt_print_char".to_string(),
+            Shape::Str => return "rt_print_str".to_string(),
+            // there's no value to print, like in an empty list
+            Shape::Nothing => return "rt_print_number".to_string(),
+            Shape::Function(..) => {
+                panic!("main returns a function, which can't be printed once compiled") // TODO: report it properly
+            }
+            Shape::Unknown => {
+                panic!("what main returns can't be inferred, so it can't be printed once compiled") // TODO: report it properly
+            }
+            Shape::Tuple(_) | Shape::List(_) | Shape::Record(_) => {}
+        }
+        if let Some(label) = self.printers.get(shape) {
+            return label.clone();
+        }
+        let label = format!("rt_print_{}", self.printers.len());
+        self.printers.insert(shape.clone(), label.clone());
+        // the value stays in %r12 and the index of a list in %rbx
+        let mut code = vec!["movq %rdi, %r12".to_string()];
+        let put = |code: &mut Vec<String>, c: char| {
+            code.push(format!("movl ${}, %edi", u32::from(c)));
+            code.push("call putchar@PLT".to_string());
+        };
+        match shape {
+            Shape::List(element) => {
+                let element = self.printer(element);
+                let (lp, first, end) = (self.label(), self.label(), self.label());
+                put(&mut code, '[');
+                code.extend([
+                    "xorl %ebx, %ebx".to_string(),
+                    format!("{lp}:"),
+                    "cmpq (%r12), %rbx".to_string(),
+                    format!("jae {end}"),
+                    "testq %rbx, %rbx".to_string(),
+                    format!("je {first}"),
+                    "call rt_print_separator".to_string(),
+                    format!("{first}:"),
+                    "movq 8(%r12,%rbx,8), %rdi".to_string(),
+                    format!("call {element}"),
+                    "incq %rbx".to_string(),
+                    format!("jmp {lp}"),
+                    format!("{end}:"),
+                ]);
+                put(&mut code, ']');
+            }
+            Shape::Tuple(elements) => {
+                put(&mut code, '(');
+                for (idx, element) in elements.iter().enumerate() {
+                    if idx > 0 {
+                        code.push("call rt_print_separator".to_string());
+                    }
+                    code.push(format!("movq {}(%r12), %rdi", 8 * idx));
+                    code.push(format!("call {}", self.printer(element)));
+                }
+                put(&mut code, ')');
+            }
+            Shape::Record(fields) => {
+                put(&mut code, '{');
+                for (idx, (field, value)) in fields.iter().enumerate() {
+                    if idx > 0 {
+                        code.push("call rt_print_separator".to_string());
+                    }
+                    code.push(format!("leaq rt_text_{}(%rip), %rdi", self.texts.len()));
+                    code.push("call rt_print_text".to_string());
+                    self.texts.push(format!("{field} = "));
+                    code.push(format!("movq {}(%r12), %rdi", 8 * idx));
+                    code.push(format!("call {}", self.printer(value)));
+                }
+                put(&mut code, '}');
+            }
+            _ => unreachable!("it's printed by the runtime"),
+        }
+        let mut out = format!("{label}:\n    pushq %rbx\n    pushq %r12\n    subq $8, %rsp\n");
+        for instr in code {
+            out += &format!("    {instr}\n");
+        }
+        self.routines
+            .push(out + "    addq $8, %rsp\n    popq %r12\n    popq %rbx\n    ret\n");
+        label
+    }
+
+    fn lambda(&mut self, arg: Binding, captured: Vec<Binding>, body: &Expr) -> usize {
+        let idx = self.lambdas.len();
+        self.lambdas.push(String::new()); // reserve the index for nested lambdas
+        let mut fun = Function::new(self, captured, vec![arg]);
+        fun.emit("movq %rdi, -8(%rbp)");
+        fun.emit("movq %rsi, -16(%rbp)");
+        fun.expr(body);
+        self.lambdas[idx] = fun.finish(&format!("tl_lambda_{idx}"));
+        idx
+    }
+
+    fn procedure(&mut self, top: &Top) -> String {
+        match top {
+            Top::Procedure {
+                name, args, body, ..
+            } => {
+                let args = args.iter().map(|(binding, _)| *binding).collect();
+                let mut fun = Function::new(self, vec![], args);
+                for idx in 0..fun.locals.len() {
+                    let slot = fun.slot(idx);
+                    match ARG_REGISTERS.get(idx) {
+                        Some(register) => fun.emit(format!("movq {register}, {slot}")),
+                        None => {
+                            fun.emit(format!("movq {}(%rbp), %rax", 16 + 8 * (idx - 6)));
+                            fun.emit(format!("movq %rax, {slot}"));
+                        }
+                    }
+                }
+                fun.expr(body);
+                fun.finish(&proc_label(*name))
+            }
+        }
+    }
+}
+
+impl<'m> Function<'m> {
+    fn new(module: &'m mut Module, env: Vec<Binding>, locals: Vec<Binding>) -> Self {
+        Self {
+            module,
+            env,
+            locals,
+            depth: 0,
+            code: vec![],
+        }
+    }
+
+    fn emit(&mut self, instr: impl Into<String>) {
+        self.code.push(instr.into());
+    }
+
+    fn label(&mut self) -> String {
+        self.module.label()
+    }
+
+    fn push(&mut self) {
+        self.depth += 1;
+        self.emit("pushq %rax");
+    }
+
+    fn pop(&mut self, register: &str) {
+        self.depth -= 1;
+        self.emit(format!("popq {register}"));
+    }
+
+    fn slot(&self, idx: usize) -> String {
+        format!("-{}(%rbp)", 8 * (idx + 2))
+    }
+
+    fn finish(self, label: &str) -> String {
+        let frame = (self.locals.len() + 1).next_multiple_of(2) * 8;
+        let mut out =
+            format!("{label}:\n    pushq %rbp\n    movq %rsp, %rbp\n    subq ${frame}, %rsp\n");
+        for instr in self.code {
+            out += &format!("    {instr}\n");
+        }
+        out + "    leave\n    ret\n"
+    }
+
+    fn binding(&mut self, binding: Binding) {
+        if let Some(idx) = self.locals.iter().position(|&b| b == binding) {
+            let slot = self.slot(idx);
+            self.emit(format!("movq {slot}, %rax"));
+        } else {
+            let Some(idx) = self.env.iter().position(|&b| b == binding) else {
+                panic!("{binding} isn't available here")
+            };
+            self.emit("movq -8(%rbp), %rax");
+            self.emit(format!("movq {}(%rax), %rax", 8 * (idx + 1)));
+        }
+    }
+
+    /// Calls `target` with the stack aligned, the arguments already being in registers
+    fn call(&mut self, target: &str) {
+        if self.depth % 2 == 1 {
+            self.emit("subq $8, %rsp");
+            self.emit(format!("call {target}"));
+            self.emit("addq $8, %rsp");
+        } else {
+            self.emit(format!("call {target}"));
+        }
+    }
+
+    fn arithmetic(&mut self, lhs: &Expr, rhs: &Expr, instr: &str) {
+        self.expr(lhs);
+        self.push();
+        self.expr(rhs);
+        self.emit("movq %rax, %rcx");
+        self.pop("%rax");
+        self.emit(format!("{instr} %ecx, %eax"));
+        self.emit("movslq %eax, %rax");
+    }
+
+    /// Allocates the record of a closure, putting its address in %rax
+    fn alloc_closure(&mut self, captured: &HashSet<Binding>) {
+        self.emit(format!("movq ${}, %rdi", 8 * (captured.len() + 1)));
+        self.call("rt_alloc");
+    }
+
+    /// Stores the code and the captures of the lambda in the record at `record`
+    fn fill_closure(&mut self, lambda: &Expr, record: &str) {
+        let Expr::LambdaFunction {
+            arg,
+            body,
+            captured,
+        } = lambda
+        else {
+            unreachable!()
+        };
+        let mut captured = captured.iter().copied().collect::<Vec<_>>();
+        captured.sort_by_key(|b| b.scope);
+        for (idx, &binding) in captured.iter().enumerate() {
+            self.binding(binding);
+            self.emit(format!("movq {record}, %rcx"));
+            self.emit(format!("movq %rax, {}(%rcx)", 8 * (idx + 1)));
+        }
+        let idx = self.module.lambda(*arg, captured, body);
+        self.emit(format!("movq {record}, %rcx"));
+        self.emit(format!("leaq tl_lambda_{idx}(%rip), %rax"));
+        self.emit("movq %rax, (%rcx)");
+    }
+
+    /// Divides lhs by rhs, the quotient ends up in %eax and the remainder in %edx
+    fn division(&mut self, lhs: &Expr, rhs: &Expr, result: &str) {
+        self.expr(lhs);
+        self.push();
+        self.expr(rhs);
+        self.emit("movq %rax, %rcx");
+        self.pop("%rax");
+        self.emit("testl %ecx, %ecx");
+        self.emit("je rt_division_by_zero");
+        // idivl faults on i32::MIN / -1, which wraps around like the other operations
+        let (divide, end) = (self.label(), self.label());
+        self.emit("cmpl $-1, %ecx");
+        self.emit(format!("jne {divide}"));
+        self.emit("negl %eax");
+        self.emit("xorl %edx, %edx");
+        self.emit(format!("jmp {end}"));
+        self.emit(format!("{divide}:"));
+        self.emit("cltd");
+        self.emit("idivl %ecx");
+        self.emit(format!("{end}:"));
+        self.emit(format!("movslq {result}, %rax"));
+    }
+
+    fn comparison(&mut self, lhs: &Expr, rhs: &Expr, set: &str) {
+        self.expr(lhs);
+        self.push();
+        self.expr(rhs);
+        self.emit("movq %rax, %rcx");
+        self.pop("%rax");
+        self.emit("cmpl %ecx, %eax");
+        self.emit(format!("{set} %al"));
+        self.emit("movzbq %al, %rax");
+    }
+
+    /// Matches the value in %rax against the pattern, putting its parts in slots
+    fn pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Binding(binding) => {
+                self.locals.push(*binding);
+                let slot = self.slot(self.locals.len() - 1);
+                self.emit(format!("movq %rax, {slot}"));
+            }
+            Pattern::Wildcard => {}
+            Pattern::Number(n) => {
+                self.emit(format!("cmpq ${n}, %rax"));
+                self.emit("jne rt_no_match");
+            }
+            Pattern::Bool(b) => {
+                self.emit(format!("cmpq ${}, %rax", i32::from(*b)));
+                self.emit("jne rt_no_match");
+            }
+            Pattern::Tuple(patterns) => {
+                self.push();
+                for (idx, pattern) in patterns.iter().enumerate() {
+                    self.emit("movq (%rsp), %rax");
+                    self.emit(format!("movq {}(%rax), %rax", 8 * idx));
+                    self.pattern(pattern);
+                }
+                self.pop("%rcx");
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Number(n) => self.emit(format!("movq ${n}, %rax")),
+            Expr::Bool(b) => self.emit(format!("movq ${}, %rax", i32::from(*b))),
+            Expr::Str(s) => {
+                let codes = s.chars().collect::<Vec<_>>();
+                self.emit(format!("movq ${}, %rdi", 8 * (codes.len() + 1)));
+                self.call("rt_alloc");
+                self.emit(format!("movq ${}, (%rax)", codes.len()));
+                for (idx, code) in codes.into_iter().enumerate() {
+                    self.emit(format!(
+                        "movq ${}, {}(%rax)",
+                        u32::from(code),
+                        8 * (idx + 1)
+                    ));
+                }
+            }
+            Expr::Char(c) => self.emit(format!("movq ${}, %rax", u32::from(*c))),
+            Expr::Tuple(elements) => {
+                self.emit(format!("movq ${}, %rdi", 8 * elements.len()));
+                self.call("rt_alloc");
+                self.push();
+                for (idx, element) in elements.iter().enumerate() {
+                    self.expr(element);
+                    self.emit("movq (%rsp), %rcx");
+                    self.emit(format!("movq %rax, {}(%rcx)", 8 * idx));
+                }
+                self.pop("%rax");
+            }
+            Expr::Record(fields) => {
+                self.emit(format!("movq ${}, %rdi", 8 * fields.len()));
+                self.call("rt_alloc");
+                self.push();
+                for (idx, (_, value)) in fields.iter().enumerate() {
+                    self.expr(value);
+                    self.emit("movq (%rsp), %rcx");
+                    self.emit(format!("movq %rax, {}(%rcx)", 8 * idx));
+                }
+                self.pop("%rax");
+            }
+            Expr::Field { record, idx, .. } => {
+                self.expr(record);
+                self.emit(format!("movq {}(%rax), %rax", 8 * idx));
+            }
+            Expr::List(elements) => {
+                self.emit(format!("movq ${}, %rdi", 8 * (elements.len() + 1)));
+                self.call("rt_alloc");
+                self.emit(format!("movq ${}, (%rax)", elements.len()));
+                self.push();
+                for (idx, element) in elements.iter().enumerate() {
+                    self.expr(element);
+                    self.emit("movq (%rsp), %rcx");
+                    self.emit(format!("movq %rax, {}(%rcx)", 8 * (idx + 1)));
+                }
+                self.pop("%rax");
+            }
+            Expr::Referal { scope } => self.binding(Binding { scope: *scope }),
+            Expr::LambdaFunction { captured, .. } => {
+                self.alloc_closure(captured);
+                self.push();
+                self.fill_closure(expr, "(%rsp)");
+                self.pop("%rax");
+            }
+            Expr::LetRec { bindings, body } => {
+                // all the records are made before they're filled, so they can capture each other
+                for (binding, value) in bindings {
+                    let Expr::LambdaFunction { captured, .. } = value else {
+                        unreachable!("the values of recursive bindings are lambdas")
+                    };
+                    self.alloc_closure(captured);
+                    self.locals.push(*binding);
+                    let slot = self.slot(self.locals.len() - 1);
+                    self.emit(format!("movq %rax, {slot}"));
+                }
+                for (binding, value) in bindings {
+                    let idx = self.locals.iter().position(|b| b == binding).unwrap();
+                    let slot = self.slot(idx);
+                    self.fill_closure(value, &slot);
+                }
+                self.expr(body);
+            }
+            Expr::BinaryOperation(lhs, kind, rhs) => match kind {
+                BinaryOpKind::Call => {
+                    self.expr(lhs);
+                    self.push();
+                    self.expr(rhs);
+                    self.emit("movq %rax, %rsi");
+                    self.pop("%rdi");
+                    self.call("*(%rdi)");
+                }
+                BinaryOpKind::Addition => self.arithmetic(lhs, rhs, "addl"),
+                BinaryOpKind::Subtraction => self.arithmetic(lhs, rhs, "subl"),
+                BinaryOpKind::Multiplication => self.arithmetic(lhs, rhs, "imull"),
+                BinaryOpKind::Division => self.division(lhs, rhs, "%eax"),
+                BinaryOpKind::Remainder => self.division(lhs, rhs, "%edx"),
+                BinaryOpKind::Concatenation => {
+                    self.expr(lhs);
+                    self.push();
+                    self.expr(rhs);
+                    self.emit("movq %rax, %rsi");
+                    self.pop("%rdi");
+                    self.call("rt_concat");
+                }
+                BinaryOpKind::Equal => self.comparison(lhs, rhs, "sete"),
+                BinaryOpKind::NotEqual => self.comparison(lhs, rhs, "setne"),
+                BinaryOpKind::Less => self.comparison(lhs, rhs, "setl"),
+                BinaryOpKind::LessEqual => self.comparison(lhs, rhs, "setle"),
+                BinaryOpKind::Greater => self.comparison(lhs, rhs, "setg"),
+                BinaryOpKind::GreaterEqual => self.comparison(lhs, rhs, "setge"),
+            },
+            Expr::ProcCall { name, args } => {
+                for arg in args {
+                    self.expr(arg);
+                    self.push();
+                }
+                // arguments past the sixth are passed on the stack, the seventh on top
+                let on_stack = args.len().saturating_sub(ARG_REGISTERS.len());
+                let padding = (self.depth + on_stack) % 2;
+                if padding == 1 {
+                    self.emit("subq $8, %rsp");
+                }
+                for idx in (ARG_REGISTERS.len()..args.len()).rev() {
+                    let pushed = padding + (args.len() - 1 - idx);
+                    let offset = 8 * (args.len() - 1 - idx + pushed);
+                    self.emit(format!("pushq {offset}(%rsp)"));
+                }
+                for (idx, register) in ARG_REGISTERS.iter().enumerate().take(args.len()) {
+                    let offset = 8 * (args.len() - 1 - idx + padding + on_stack);
+                    self.emit(format!("movq {offset}(%rsp), {register}"));
+                }
+                self.emit(format!("call {}", proc_label(*name)));
+                let words = args.len() + padding + on_stack;
+                if words > 0 {
+                    self.emit(format!("addq ${}, %rsp", 8 * words));
+                }
+                self.depth -= args.len();
+            }
+            Expr::Builtin {
+                builtin: Builtin::Length,
+                args,
+            } => {
+                self.expr(args[0]);
+                self.emit("movq (%rax), %rax");
+            }
+            Expr::Builtin { builtin, args } => {
+                for arg in args {
+                    self.expr(arg);
+                    self.push();
+                }
+                for register in ARG_REGISTERS[..args.len()].iter().rev() {
+                    self.pop(register);
+                }
+                self.call(match builtin {
+                    Builtin::Index => "rt_index",
+                    Builtin::Map => "rt_map",
+                    Builtin::Fold => "rt_fold",
+                    Builtin::Push => "rt_push",
+                    Builtin::Length => unreachable!("the length is the first word"),
+                });
+            }
+            Expr::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let (otherwise_label, end) = (self.label(), self.label());
+                self.expr(condition);
+                self.emit("testq %rax, %rax");
+                self.emit(format!("je {otherwise_label}"));
+                self.expr(then);
+                self.emit(format!("jmp {end}"));
+                self.emit(format!("{otherwise_label}:"));
+                self.expr(otherwise);
+                self.emit(format!("{end}:"));
+            }
+            Expr::Destructure {
+                value,
+                pattern,
+                body,
+            } => {
+                self.expr(value);
+                self.pattern(pattern);
+                self.expr(body);
+            }
+        }
+    }
+}
+
+impl FinalLevel<'_> {
+    pub fn emit_asm(&self) -> String {
+        let mut module = Module::default();
+        let procs = self
+            .tops
+            .iter()
+            .map(|top| module.procedure(top))
+            .collect::<Vec<_>>();
+        let main = Shapes::new(&self.tops).main();
+        let printer = module.printer(&main);
+        let mut out = RUNTIME.to_string();
+        out += &format!(
+            "\n    .globl main\nmain:\n    pushq %rbp\n    movq %rsp, %rbp\n    leaq rt_heap(%rip), %rax\n    movq %rax, rt_heap_ptr(%rip)\n    call tl_main\n    movq %rax, %rdi\n    call {printer}\n    movl $10, %edi\n    call putchar@PLT\n    xorl %eax, %eax\n    popq %rbp\n    ret\n"
+        );
+        for fun in procs.iter().chain(&module.lambdas).chain(&module.routines) {
+            out += fun;
+        }
+        out += RODATA;
+        for (idx, text) in module.texts.iter().enumerate() {
+            out += &format!("rt_text_{idx}:\n    .string \"{text}\"\n");
+        }
+        out += &format!(
+            "\n    .bss\n    .p2align 4\nrt_heap_ptr:\n    .zero 8\nrt_heap:\n    .zero {HEAP_SIZE}\nrt_heap_end:\n"
+        );
+        out + "\n    .section .note.GNU-stack,\"\",@progbits\n"
+    }
+}
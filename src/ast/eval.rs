@@ -1,22 +1,171 @@
-use super::level1;
+use super::{Arenas, level0, level1};
 use crate::ast::level0::GlobalSymbol;
-use crate::common::Scope;
+use crate::common::{Ident, Quoted, Scope};
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// How arguments are passed to functions and procs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Strategy {
+    /// Call-by-value: arguments are evaluated before the call
+    #[default]
+    Strict,
+    /// Call-by-need: arguments are evaluated the first time they're needed
+    Lazy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Options {
+    pub strategy: Strategy,
+    pub normalize: bool,   // print functions as their normal forms
+    pub fuel: Option<u64>, // the maximum number of steps
+    pub timeout: Option<Duration>,
+    pub max_depth: Option<usize>, // the maximum number of nested calls
+}
+
+/// Why evaluation stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    OutOfFuel,
+    Timeout,
+    TooDeep,
+    DivisionByZero,
+    NoMatch,
+    OutOfBounds,
+    NoField(Ident),
+    WrongOperands(level1::BinaryOpKind),
+    NotAFunction,
+    NotABool,
+    WrongArguments(level1::Builtin),
+}
+
+impl std::fmt::Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::OutOfFuel => write!(f, "ran out of fuel"),
+            Self::Timeout => write!(f, "ran out of time"),
+            Self::TooDeep => write!(f, "too many nested calls"),
+            Self::DivisionByZero => write!(f, "divided by zero"),
+            Self::NoMatch => write!(f, "a value didn't match its pattern"),
+            Self::OutOfBounds => write!(f, "indexed past the end of a list"),
+            Self::NoField(field) => write!(f, "a value had no field {field}"),
+            Self::NotAFunction => write!(f, "a value that isn't a function was called"),
+            Self::NotABool => write!(f, "the condition of an if wasn't a bool"),
+            Self::WrongArguments(level1::Builtin::Index) => {
+                write!(
+                    f,
+                    "something that isn't a list was indexed, or not by a number"
+                )
+            }
+            Self::WrongArguments(builtin) => {
+                write!(f, "`{builtin}!` can't be called with these values")
+            }
+            Self::WrongOperands(kind) => {
+                let operator = kind.to_string();
+                write!(f, "`{}` can't be applied to these values", operator.trim())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error {
+    pub reason: Reason,
+    pub proc: Option<GlobalSymbol>, // the innermost running proc
+    pub steps: u64,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} after {} steps", self.reason, self.steps)?;
+        match self.proc {
+            Some(proc) => write!(f, " while running {proc}"),
+            None => Ok(()),
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Value<'a> {
     Number(i32),
+    Bool(bool),
+    Str(Rc<str>), // shared, so copying it around is cheap
+    Char(char),
+    Tuple(Vec<Self>),
+    List(Vec<Self>),
+    Record(Vec<(Ident, Self)>), // in the order the record declares the fields
     Function {
         arg: level1::Binding,
-        body: level1::Expr<'a>,
+        body: &'a level1::Expr<'a>,
         captures: HashMap<level1::Binding, Self>,
     },
+    Thunk(Rc<RefCell<Thunk<'a>>>), // shared, so it's evaluated at most once
+    Neutral(Neutral<'a>),
+}
+
+/// A computation stuck on an unknown argument, only appears while reading back functions.
+/// The parts are shared, as they can be as deep as the recursion that made them,
+/// and cloning or dropping them would recurse as deep
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Neutral<'a> {
+    Variable(Ident),
+    Application(Rc<Self>, Rc<Value<'a>>),
+    Arithmetic(Rc<Value<'a>>, level1::BinaryOpKind, Rc<Value<'a>>),
+    If(Rc<Value<'a>>, Rc<Value<'a>>, Rc<Value<'a>>), // the branches are delayed
+    Builtin(level1::Builtin, Vec<Value<'a>>),
+    Field(Rc<Self>, Ident),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Thunk<'a> {
+    Delayed {
+        expr: &'a level1::Expr<'a>,
+        bindings: HashMap<Scope, Value<'a>>,
+    },
+    Forcing, // being evaluated right now
+    Forced(Value<'a>),
 }
 
 impl std::fmt::Display for Value<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Number(n) => write!(f, "{n}"),
+            Self::Bool(b) => write!(f, "{b}"),
+            // quoted, so it reads back as the literal
+            Self::Str(s) => write!(f, "{}", Quoted(s, '"')),
+            Self::Char(c) => write!(f, "{}", Quoted(c.encode_utf8(&mut [0; 4]), '\'')),
+            Self::Tuple(elements) => {
+                write!(f, "(")?;
+                for (idx, element) in elements.iter().enumerate() {
+                    write!(f, "{element}")?;
+                    if idx < elements.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, ")")
+            }
+            Self::List(elements) => {
+                write!(f, "[")?;
+                for (idx, element) in elements.iter().enumerate() {
+                    write!(f, "{element}")?;
+                    if idx < elements.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, "]")
+            }
+            Self::Record(fields) => {
+                write!(f, "{{")?;
+                for (idx, (field, value)) in fields.iter().enumerate() {
+                    write!(f, "{field} = {value}")?;
+                    if idx < fields.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, "}}")
+            }
             Self::Function {
                 arg,
                 body,
@@ -28,76 +177,551 @@ impl std::fmt::Display for Value<'_> {
                 }
                 write!(f, "{body}")
             }
+            // it's borrowed while it's printed, so a recursive function isn't printed inside itself
+            Self::Thunk(thunk) => match thunk.try_borrow_mut().as_deref() {
+                Ok(Thunk::Forced(value)) => write!(f, "{value}"),
+                Ok(Thunk::Delayed { expr, .. }) => write!(f, "lazy {{ {expr} }}"),
+                Ok(Thunk::Forcing) => write!(f, "lazy {{ ... }}"),
+                Err(_) => write!(f, "..."),
+            },
+            Self::Neutral(neutral) => write!(f, "{neutral}"),
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Default)]
+impl std::fmt::Display for Neutral<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Variable(name) => write!(f, "{name}"),
+            Self::Application(function, arg) => write!(f, "({function} {arg})"),
+            Self::Arithmetic(lhs, kind, rhs) => write!(f, "({lhs}{kind}{rhs})"),
+            Self::If(condition, then, otherwise) => {
+                write!(f, "(if {condition} then {then} else {otherwise})")
+            }
+            Self::Field(record, field) => write!(f, "({record}.{field})"),
+            Self::Builtin(level1::Builtin::Index, args) => {
+                write!(f, "({}.[{}])", args[0], args[1])
+            }
+            Self::Builtin(builtin, args) => {
+                write!(f, "{builtin}!(")?;
+                for (idx, arg) in args.iter().enumerate() {
+                    write!(f, "{arg}")?;
+                    if idx < args.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct State<'a> {
+    options: Options,
+    names: HashMap<Scope, Ident>, // source names of bindings, used for reading back
+    arenas: &'a Arenas<'a>,
     bindings: HashMap<Scope, Value<'a>>,
-    globals: HashMap<GlobalSymbol<'a>, level1::Top<'a>>,
+    globals: HashMap<GlobalSymbol, &'a level1::Top<'a>>,
+    read_back_names: Vec<Ident>, // the variables of the functions being read back
+    steps: u64,
+    deadline: Option<Instant>,
+    depth: usize,             // of nested calls
+    procs: Vec<GlobalSymbol>, // the running procs, innermost last
 }
 
 impl<'s> State<'s> {
-    pub fn eval_expr(&mut self, expr: level1::Expr<'s>) -> Value<'s> {
-        match expr {
-            level1::Expr::Number(x) => Value::Number(x),
+    pub fn new(options: Options, names: HashMap<Scope, Ident>, arenas: &'s Arenas<'s>) -> Self {
+        Self {
+            options,
+            names,
+            arenas,
+            bindings: HashMap::new(),
+            globals: HashMap::new(),
+            read_back_names: vec![],
+            steps: 0,
+            deadline: options.timeout.map(|timeout| Instant::now() + timeout),
+            depth: 0,
+            procs: vec![],
+        }
+    }
+
+    fn stop(&self, reason: Reason) -> Error {
+        Error {
+            reason,
+            proc: self.procs.last().copied(),
+            steps: self.steps,
+        }
+    }
+
+    /// Counts a step, stopping if a limit is exceeded
+    fn tick(&mut self) -> Result<(), Error> {
+        if self.options.fuel.is_some_and(|fuel| self.steps >= fuel) {
+            return Err(self.stop(Reason::OutOfFuel));
+        }
+        self.steps += 1;
+        // looking at the clock is slow, so don't do it every step
+        if self.steps.is_multiple_of(1024) && self.deadline.is_some_and(|d| Instant::now() > d) {
+            return Err(self.stop(Reason::Timeout));
+        }
+        Ok(())
+    }
+
+    /// Runs a call, stopping if there are too many nested ones
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+        if self.options.max_depth.is_some_and(|max| self.depth >= max) {
+            return Err(self.stop(Reason::TooDeep));
+        }
+        self.depth += 1;
+        // deep recursion is fine, as long as it's below max_depth.
+        // The red zone fits the frames between two nested calls, which are big in debug builds
+        let res = stacker::maybe_grow(256 * 1024, 4 * 1024 * 1024, || f(self));
+        self.depth -= 1;
+        res
+    }
+
+    /// Evaluates an argument according to the strategy
+    fn delay(&mut self, expr: &'s level1::Expr<'s>) -> Result<Value<'s>, Error> {
+        match (self.options.strategy, expr) {
+            (Strategy::Strict, _)
+            | (
+                Strategy::Lazy,
+                level1::Expr::Number(_)
+                | level1::Expr::Bool(_)
+                | level1::Expr::Str(_)
+                | level1::Expr::Char(_)
+                | level1::Expr::LambdaFunction { .. }
+                | level1::Expr::Referal { .. },
+            ) => self.eval_expr(expr),
+            (Strategy::Lazy, _) => Ok(self.suspend(expr)),
+        }
+    }
+
+    /// Delays evaluating the expression, whatever the strategy
+    fn suspend(&self, expr: &'s level1::Expr<'s>) -> Value<'s> {
+        Value::Thunk(Rc::new(RefCell::new(Thunk::Delayed {
+            expr,
+            bindings: self.bindings.clone(),
+        })))
+    }
+
+    /// Evaluates thunks until the value is a number, a bool, a string, a char, a tuple, a list,
+    /// a record or a function
+    pub fn force(&mut self, value: Value<'s>) -> Result<Value<'s>, Error> {
+        let Value::Thunk(thunk) = value else {
+            return Ok(value);
+        };
+        let delayed = std::mem::replace(&mut *thunk.borrow_mut(), Thunk::Forcing);
+        let forced = match delayed {
+            Thunk::Forced(value) => value,
+            Thunk::Forcing => panic!("a value depends on itself"),
+            Thunk::Delayed { expr, bindings } => {
+                let old_bindings = std::mem::replace(&mut self.bindings, bindings);
+                let value = self.eval_expr(expr);
+                self.bindings = old_bindings;
+                self.force(value?)?
+            }
+        };
+        *thunk.borrow_mut() = Thunk::Forced(forced.clone());
+        Ok(forced)
+    }
+
+    /// Forces the value and the elements of tuples, lists and records in it, so it can be printed
+    pub fn force_all(&mut self, value: Value<'s>) -> Result<Value<'s>, Error> {
+        match self.force(value)? {
+            Value::Tuple(elements) => Ok(Value::Tuple(
+                elements
+                    .into_iter()
+                    .map(|element| self.force_all(element))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Value::List(elements) => Ok(Value::List(
+                elements
+                    .into_iter()
+                    .map(|element| self.force_all(element))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Value::Record(fields) => Ok(Value::Record(
+                fields
+                    .into_iter()
+                    .map(|(field, value)| Ok((field, self.force_all(value)?)))
+                    .collect::<Result<_, _>>()?,
+            )),
+            value => Ok(value),
+        }
+    }
+
+    /// Binds the names of the pattern to the parts of the value,
+    /// forcing only the parts it has to look at
+    fn bind_pattern(&mut self, pattern: &level1::Pattern, value: Value<'s>) -> Result<(), Error> {
+        use level1::Pattern;
+        match pattern {
+            Pattern::Binding(binding) => {
+                self.bindings.insert(binding.scope, value);
+            }
+            Pattern::Wildcard => {}
+            Pattern::Number(n) => match self.force(value)? {
+                Value::Number(m) if m == *n => {}
+                _ => return Err(self.stop(Reason::NoMatch)),
+            },
+            Pattern::Bool(b) => match self.force(value)? {
+                Value::Bool(c) if c == *b => {}
+                _ => return Err(self.stop(Reason::NoMatch)),
+            },
+            Pattern::Tuple(patterns) => match self.force(value)? {
+                Value::Tuple(elements) if elements.len() == patterns.len() => {
+                    for (pattern, element) in patterns.iter().zip(elements) {
+                        self.bind_pattern(pattern, element)?;
+                    }
+                }
+                Value::Neutral(neutral) => {
+                    panic!("{neutral} can't be destructured while normalizing") // TODO: report it properly
+                }
+                // a tuple of another length, or not a tuple
+                _ => return Err(self.stop(Reason::NoMatch)),
+            },
+        }
+        Ok(())
+    }
+
+    /// Turns a value back into an expression, evaluating under function binders,
+    /// so functions are printed as their normal forms
+    pub fn read_back(&mut self, value: Value<'s>) -> Result<level0::Expr<'s>, Error> {
+        // a normal form can be infinite, so it's limited like evaluation
+        self.tick()?;
+        self.nested(|this| this.read_back_now(value))
+    }
+
+    fn read_back_now(&mut self, value: Value<'s>) -> Result<level0::Expr<'s>, Error> {
+        Ok(match self.force(value)? {
+            Value::Number(n) => level0::Expr::Number(n.into()),
+            Value::Bool(b) => level0::Expr::Bool(b),
+            Value::Str(s) => level0::Expr::Str(self.arenas.strings.alloc(s.to_string())),
+            Value::Char(c) => level0::Expr::Char(c),
+            Value::Tuple(elements) => {
+                let elements = elements
+                    .into_iter()
+                    .map(|element| self.read_back(element))
+                    .collect::<Result<Vec<_>, _>>()?;
+                level0::Expr::Tuple(self.arenas.level0.alloc_slice(elements))
+            }
+            Value::List(elements) => {
+                let elements = elements
+                    .into_iter()
+                    .map(|element| self.read_back(element))
+                    .collect::<Result<Vec<_>, _>>()?;
+                level0::Expr::List(self.arenas.level0.alloc_slice(elements))
+            }
+            Value::Record(fields) => {
+                let fields = fields
+                    .into_iter()
+                    .map(|(field, value)| Ok((field, self.read_back_ref(value)?)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                level0::Expr::Record(self.arenas.fields.alloc_slice(fields))
+            }
+            function @ Value::Function { arg, body, .. } => {
+                let start = self.read_back_names.len();
+                // an argument that was a pattern is read back as one,
+                // with variables for its names
+                let (arg, variable) = match body {
+                    level1::Expr::Destructure {
+                        value: level1::Expr::Referal { scope },
+                        pattern,
+                        ..
+                    } if *scope == arg.scope && !self.names.contains_key(scope) => {
+                        self.read_back_pattern(pattern)
+                    }
+                    _ => self.read_back_pattern(&level1::Pattern::Binding(arg)),
+                };
+                let body = self.apply(function, variable)?;
+                let body = self.read_back(body)?;
+                self.read_back_names.truncate(start);
+                level0::Expr::LambdaFunction {
+                    args: self.arenas.patterns.alloc_slice([arg]),
+                    body: self.arenas.level0.alloc(body),
+                    notation: level0::Notation::Prefix,
+                }
+            }
+            Value::Neutral(Neutral::Variable(name)) => level0::Expr::Referal(name),
+            Value::Neutral(Neutral::Application(function, arg)) => level0::Expr::BinaryOperation(
+                self.read_back_ref(Value::Neutral(Rc::unwrap_or_clone(function)))?,
+                level0::BinaryOpKind::Call,
+                self.read_back_ref(Rc::unwrap_or_clone(arg))?,
+            ),
+            Value::Neutral(Neutral::Arithmetic(lhs, kind, rhs)) => level0::Expr::BinaryOperation(
+                self.read_back_ref(Rc::unwrap_or_clone(lhs))?,
+                kind.into(),
+                self.read_back_ref(Rc::unwrap_or_clone(rhs))?,
+            ),
+            Value::Neutral(Neutral::If(condition, then, otherwise)) => level0::Expr::If {
+                condition: self.read_back_ref(Rc::unwrap_or_clone(condition))?,
+                then: self.read_back_ref(Rc::unwrap_or_clone(then))?,
+                otherwise: self.read_back_ref(Rc::unwrap_or_clone(otherwise))?,
+            },
+            Value::Neutral(Neutral::Field(record, field)) => level0::Expr::Field {
+                record: self.read_back_ref(Value::Neutral(Rc::unwrap_or_clone(record)))?,
+                field,
+            },
+            Value::Neutral(Neutral::Builtin(builtin, args)) => {
+                let args = args
+                    .into_iter()
+                    .map(|arg| self.read_back(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                builtin.resugar(self.arenas.level0.alloc_slice(args))
+            }
+            Value::Thunk(_) => unreachable!("forced values aren't thunks"),
+        })
+    }
+
+    /// The pattern with fresh names, and the value with a variable for every name
+    fn read_back_pattern(&mut self, pattern: &level1::Pattern) -> (level0::Binding<'s>, Value<'s>) {
+        use level1::Pattern;
+        match pattern {
+            Pattern::Binding(binding) => {
+                let name = self.fresh_name(*binding);
+                self.read_back_names.push(name);
+                let variable = Value::Neutral(Neutral::Variable(name));
+                (level0::Binding::Name(name), variable)
+            }
+            Pattern::Wildcard => {
+                let variable = Value::Neutral(Neutral::Variable(Ident::new("_")));
+                (level0::Binding::Wildcard, variable)
+            }
+            // the literal itself, so it matches
+            Pattern::Number(n) => (level0::Binding::Number((*n).into()), Value::Number(*n)),
+            Pattern::Bool(b) => (level0::Binding::Bool(*b), Value::Bool(*b)),
+            Pattern::Tuple(patterns) => {
+                let (patterns, values): (Vec<_>, _) = patterns
+                    .iter()
+                    .map(|pattern| self.read_back_pattern(pattern))
+                    .unzip();
+                let patterns = self.arenas.patterns.alloc_slice(patterns);
+                (level0::Binding::Tuple(patterns), Value::Tuple(values))
+            }
+        }
+    }
+
+    fn read_back_ref(&mut self, value: Value<'s>) -> Result<&'s level0::Expr<'s>, Error> {
+        Ok(self.arenas.level0.alloc(self.read_back(value)?))
+    }
+
+    /// The source name of the binding, numbered if an outer function being read back uses it
+    fn fresh_name(&self, binding: level1::Binding) -> Ident {
+        let name = self
+            .names
+            .get(&binding.scope)
+            .copied()
+            .unwrap_or(Ident::new("x"));
+        name.fresh(&self.read_back_names)
+    }
+
+    fn apply(&mut self, function: Value<'s>, passed: Value<'s>) -> Result<Value<'s>, Error> {
+        match function {
+            Value::Function {
+                arg,
+                body,
+                captures,
+            } => {
+                // Functions are pure. Refering to stuff from the outer scope should be
+                // done with capturing! For that reason, we empty out self.bindings
+                let old_bindings = std::mem::take(&mut self.bindings);
+                self.bindings.insert(arg.scope, passed);
+                self.bindings
+                    .extend(captures.into_iter().map(|(k, v)| (k.scope, v)));
+                let res = self.nested(|this| this.eval_expr(body));
+                self.bindings = old_bindings;
+                res
+            }
+            Value::Neutral(function) => Ok(Value::Neutral(Neutral::Application(
+                Rc::new(function),
+                Rc::new(passed),
+            ))),
+            _ => Err(self.stop(Reason::NotAFunction)),
+        }
+    }
+
+    /// Runs the builtin, forcing only the arguments it looks at
+    fn builtin(
+        &mut self,
+        builtin: level1::Builtin,
+        mut args: Vec<Value<'s>>,
+    ) -> Result<Value<'s>, Error> {
+        use level1::Builtin;
+        // the first argument is the string or list it works on
+        let subject = self.force(args.remove(0))?;
+        Ok(match (builtin, subject) {
+            (_, subject @ Value::Neutral(_)) => {
+                args.insert(0, subject);
+                Value::Neutral(Neutral::Builtin(builtin, args))
+            }
+            (Builtin::Length, Value::Str(s)) => Value::Number(s.chars().count() as i32),
+            (Builtin::Length, Value::List(elements)) => Value::Number(elements.len() as i32),
+            (Builtin::Index, Value::List(elements)) => match self.force(args.remove(0))? {
+                Value::Number(idx) => match usize::try_from(idx).ok().and_then(|i| elements.get(i))
+                {
+                    Some(element) => element.clone(),
+                    None => return Err(self.stop(Reason::OutOfBounds)),
+                },
+                idx @ Value::Neutral(_) => {
+                    Value::Neutral(Neutral::Builtin(builtin, vec![Value::List(elements), idx]))
+                }
+                _ => return Err(self.stop(Reason::WrongArguments(builtin))),
+            },
+            (Builtin::Map, Value::List(elements)) => {
+                let function = self.force(args.remove(0))?;
+                Value::List(
+                    elements
+                        .into_iter()
+                        .map(|element| self.apply(function.clone(), element))
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+            (Builtin::Fold, Value::List(elements)) => {
+                let function = self.force(args.remove(1))?;
+                let init = args.remove(0);
+                elements.into_iter().try_fold(init, |acc, element| {
+                    let partial = self.apply(function.clone(), acc)?;
+                    let partial = self.force(partial)?;
+                    self.apply(partial, element)
+                })?
+            }
+            (Builtin::Push, Value::List(mut elements)) => {
+                elements.extend(args);
+                Value::List(elements)
+            }
+            _ => return Err(self.stop(Reason::WrongArguments(builtin))),
+        })
+    }
+
+    pub fn eval_expr(&mut self, expr: &'s level1::Expr<'s>) -> Result<Value<'s>, Error> {
+        self.tick()?;
+        Ok(match expr {
+            level1::Expr::Number(x) => Value::Number(*x),
+            level1::Expr::Bool(b) => Value::Bool(*b),
+            level1::Expr::Str(s) => Value::Str(Rc::from(*s)),
+            level1::Expr::Char(c) => Value::Char(*c),
+            level1::Expr::Tuple(elements) => Value::Tuple(
+                elements
+                    .iter()
+                    .map(|element| self.delay(element))
+                    .collect::<Result<_, _>>()?,
+            ),
+            level1::Expr::List(elements) => Value::List(
+                elements
+                    .iter()
+                    .map(|element| self.delay(element))
+                    .collect::<Result<_, _>>()?,
+            ),
+            level1::Expr::Record(fields) => Value::Record(
+                fields
+                    .iter()
+                    .map(|(field, value)| Ok((*field, self.delay(value)?)))
+                    .collect::<Result<_, _>>()?,
+            ),
+            level1::Expr::Field { record, field, idx } => {
+                let record = self.eval_expr(record)?;
+                match self.force(record)? {
+                    Value::Record(fields) => match fields.get(*idx) {
+                        Some((name, value)) if name == field => value.clone(),
+                        _ => return Err(self.stop(Reason::NoField(*field))),
+                    },
+                    Value::Neutral(record) => {
+                        Value::Neutral(Neutral::Field(Rc::new(record), *field))
+                    }
+                    _ => return Err(self.stop(Reason::NoField(*field))),
+                }
+            }
             level1::Expr::LambdaFunction {
                 arg,
                 body,
                 captured,
             } => Value::Function {
-                arg,
-                body: *body,
+                arg: *arg,
+                body,
                 captures: captured
-                    .into_iter()
-                    .map(|s| (s, self.bindings.get(&s.scope).unwrap().clone()))
+                    .iter()
+                    .map(|s| (*s, self.bindings.get(&s.scope).unwrap().clone()))
                     .collect(),
             },
 
-            level1::Expr::Referal { scope } => self.bindings.get(&scope).unwrap().clone(),
+            level1::Expr::LetRec { bindings, body } => {
+                // the functions capture the cells, which are filled once they're all made
+                let cells = bindings
+                    .iter()
+                    .map(|(binding, _)| {
+                        let cell = Rc::new(RefCell::new(Thunk::Forcing));
+                        self.bindings
+                            .insert(binding.scope, Value::Thunk(cell.clone()));
+                        cell
+                    })
+                    .collect::<Vec<_>>();
+                for ((_, value), cell) in bindings.iter().zip(cells) {
+                    let value = self.eval_expr(value)?;
+                    *cell.borrow_mut() = Thunk::Forced(value);
+                }
+                self.eval_expr(body)?
+            }
+            level1::Expr::Referal { scope } => self.bindings.get(scope).unwrap().clone(),
             level1::Expr::BinaryOperation(lhs, kind, rhs) => {
                 use level1::BinaryOpKind as Op;
-                let lhs = self.eval_expr(*lhs);
-                let rhs = self.eval_expr(*rhs);
-                match kind {
-                    Op::Addition => match (lhs, rhs) {
-                        (Value::Number(x), Value::Number(y)) => Value::Number(x + y),
-                        _ => panic!(),
-                    },
-                    Op::Multiplication => match (lhs, rhs) {
-                        (Value::Number(x), Value::Number(y)) => Value::Number(x * y),
-                        _ => panic!(),
+                let lhs = self.eval_expr(lhs)?;
+                let lhs = self.force(lhs)?;
+                let rhs = match kind {
+                    Op::Call => self.delay(rhs)?,
+                    _ => {
+                        let rhs = self.eval_expr(rhs)?;
+                        self.force(rhs)?
+                    }
+                };
+                match (*kind, lhs, rhs) {
+                    (Op::Call, function, passed) => self.apply(function, passed)?,
+                    // numbers wrap around on overflow, as they do when compiled
+                    (Op::Addition, Value::Number(x), Value::Number(y)) => {
+                        Value::Number(x.wrapping_add(y))
+                    }
+                    (Op::Subtraction, Value::Number(x), Value::Number(y)) => {
+                        Value::Number(x.wrapping_sub(y))
+                    }
+                    (Op::Multiplication, Value::Number(x), Value::Number(y)) => {
+                        Value::Number(x.wrapping_mul(y))
+                    }
+                    (Op::Division | Op::Remainder, Value::Number(_), Value::Number(0)) => {
+                        return Err(self.stop(Reason::DivisionByZero));
+                    }
+                    (Op::Division, Value::Number(x), Value::Number(y)) => {
+                        Value::Number(x.wrapping_div(y))
+                    }
+                    (Op::Remainder, Value::Number(x), Value::Number(y)) => {
+                        Value::Number(x.wrapping_rem(y))
+                    }
+                    (kind, Value::Number(x), Value::Number(y)) => match kind.compare(x, y) {
+                        Some(result) => Value::Bool(result),
+                        None => return Err(self.stop(Reason::WrongOperands(kind))),
                     },
-                    Op::Call => match (lhs, rhs) {
-                        (
-                            Value::Function {
-                                arg,
-                                body,
-                                captures,
-                            },
-                            passed,
-                        ) => {
-                            // Functions are pure. Refering to stuff from the outer scope should be
-                            // done with capturing! For that reason, we empty out self.bindings
-                            let old_bindings = std::mem::take(&mut self.bindings);
-                            self.bindings.insert(arg.scope, passed);
-                            self.bindings
-                                .extend(captures.into_iter().map(|(k, v)| (k.scope, v)));
-                            let res = self.eval_expr(body);
-                            self.bindings = old_bindings;
-                            res
+                    (Op::Equal, Value::Bool(x), Value::Bool(y)) => Value::Bool(x == y),
+                    (Op::NotEqual, Value::Bool(x), Value::Bool(y)) => Value::Bool(x != y),
+                    (Op::Concatenation, Value::Str(x), Value::Str(y)) => {
+                        Value::Str(Rc::from(format!("{x}{y}")))
+                    }
+                    // chars are ordered by their codes
+                    (kind, Value::Char(x), Value::Char(y)) => {
+                        match kind.compare(x as i32, y as i32) {
+                            Some(result) => Value::Bool(result),
+                            None => return Err(self.stop(Reason::WrongOperands(kind))),
                         }
-                        _ => panic!(),
-                    },
+                    }
+                    (kind, lhs @ Value::Neutral(_), rhs) | (kind, lhs, rhs @ Value::Neutral(_)) => {
+                        Value::Neutral(Neutral::Arithmetic(Rc::new(lhs), kind, Rc::new(rhs)))
+                    }
+                    (kind, _, _) => return Err(self.stop(Reason::WrongOperands(kind))),
                 }
             }
             level1::Expr::ProcCall { name, args } => {
                 let passed_args = args
-                    .into_iter()
-                    .map(|x| self.eval_expr(x))
-                    .collect::<Vec<_>>();
-                let top = self.globals.get(&name).unwrap();
+                    .iter()
+                    .map(|x| self.delay(x))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let top = self.globals[name];
                 match top {
                     level1::Top::Procedure {
                         args,
@@ -111,18 +735,54 @@ impl<'s> State<'s> {
                                 .zip(passed_args)
                                 .map(|((name, _type), value)| (name.scope, value)),
                         );
-                        let res = self.eval_expr(body.clone());
+                        self.procs.push(*name);
+                        let res = self.nested(|this| this.eval_expr(body));
+                        self.procs.pop();
                         self.bindings = old_bindings;
-                        res
+                        res?
                     }
                 }
             }
-        }
+            level1::Expr::Builtin { builtin, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.delay(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.builtin(*builtin, args)?
+            }
+            level1::Expr::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let condition = self.eval_expr(condition)?;
+                // only the taken branch is evaluated
+                match self.force(condition)? {
+                    Value::Bool(true) => self.eval_expr(then)?,
+                    Value::Bool(false) => self.eval_expr(otherwise)?,
+                    condition @ Value::Neutral(_) => Value::Neutral(Neutral::If(
+                        Rc::new(condition),
+                        Rc::new(self.suspend(then)),
+                        Rc::new(self.suspend(otherwise)),
+                    )),
+                    _ => return Err(self.stop(Reason::NotABool)),
+                }
+            }
+            level1::Expr::Destructure {
+                value,
+                pattern,
+                body,
+            } => {
+                let value = self.delay(value)?;
+                self.bind_pattern(pattern, value)?;
+                self.eval_expr(body)?
+            }
+        })
     }
-    pub fn eval_top(&mut self, top: level1::Top<'s>) {
+    pub fn eval_top(&mut self, top: &'s level1::Top<'s>) {
         match top {
             level1::Top::Procedure { name, .. } => {
-                self.globals.insert(name, top);
+                self.globals.insert(*name, top);
             }
         }
     }
@@ -1,19 +1,78 @@
-use crate::common::Ident;
+use crate::common::{Ident, Quoted};
 
+/// A number literal, with the type of its suffix if it has one, like `10i32`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Number<'a> {
+    pub value: i32,
+    pub suffix: Option<&'a Type>,
+}
+
+impl From<i32> for Number<'_> {
+    fn from(value: i32) -> Self {
+        Self {
+            value,
+            suffix: None,
+        }
+    }
+}
+
+impl std::fmt::Display for Number<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.value)?;
+        match self.suffix {
+            Some(suffix) => write!(f, "{suffix}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The equivalent of a rust pattern
 #[derive(Debug, Clone, PartialEq, Eq, Copy, Hash)]
-pub struct Binding<'a>(pub Ident<'a>); // equivalent of a rust pattern, so will grow
+pub enum Binding<'a> {
+    Name(Ident),
+    Wildcard,
+    Number(Number<'a>),
+    Bool(bool),
+    Tuple(&'a [Self]),
+}
+
+impl Binding<'_> {
+    /// Whether some values don't match it
+    pub fn is_refutable(&self) -> bool {
+        match self {
+            Self::Name(_) | Self::Wildcard => false,
+            Self::Number(_) | Self::Bool(_) => true,
+            Self::Tuple(elements) => elements.iter().any(Self::is_refutable),
+        }
+    }
+}
 
 impl std::fmt::Display for Binding<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        match self {
+            Self::Name(name) => write!(f, "{name}"),
+            Self::Wildcard => write!(f, "_"),
+            Self::Number(n) => write!(f, "{n}"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Tuple(elements) => {
+                write!(f, "(")?;
+                for (idx, element) in elements.iter().enumerate() {
+                    write!(f, "{element}")?;
+                    if idx < elements.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, ")")
+            }
+        }
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Copy, Hash)]
-pub struct GlobalSymbol<'a>(pub Ident<'a>); // maybe will grow with a mod or crate prefix, will be
+pub struct GlobalSymbol(pub Ident); // maybe will grow with a mod or crate prefix, will be
 // imported, so can't depend on global state and can't have an Id ever
 
-impl std::fmt::Display for GlobalSymbol<'_> {
+impl std::fmt::Display for GlobalSymbol {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{}", self.0)
     }
@@ -25,14 +84,52 @@ pub enum Type {
     #[default]
     Unit, // one value
     I32,
+    Bool,
+    Str,
+    Char,
+    Tuple(Vec<Self>),
+    List(Box<Self>), // [i32]
+    Named(Ident),    // a record
+}
+
+impl Type {
+    /// The numbers of the type, if it's a number type
+    pub fn range(&self) -> Option<std::ops::RangeInclusive<i64>> {
+        match self {
+            Self::I32 => Some(i32::MIN.into()..=i32::MAX.into()),
+            Self::Never
+            | Self::Unit
+            | Self::Bool
+            | Self::Str
+            | Self::Char
+            | Self::Tuple(_)
+            | Self::List(_)
+            | Self::Named(_) => None,
+        }
+    }
 }
 
 impl std::fmt::Display for Type {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             Self::I32 => write!(f, "i32"),
+            Self::Bool => write!(f, "bool"),
+            Self::Str => write!(f, "str"),
+            Self::Char => write!(f, "char"),
             Self::Unit => write!(f, "()"),
             Self::Never => write!(f, "!"),
+            Self::Tuple(elements) => {
+                write!(f, "(")?;
+                for (idx, element) in elements.iter().enumerate() {
+                    write!(f, "{element}")?;
+                    if idx < elements.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, ")")
+            }
+            Self::List(element) => write!(f, "[{element}]"),
+            Self::Named(name) => write!(f, "{name}"),
         }
     }
 }
@@ -40,22 +137,72 @@ impl std::fmt::Display for Type {
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Top<'a> {
     Procedure {
-        name: GlobalSymbol<'a>,
+        doc: Vec<String>, // the lines of its `///` comments, for tooling
+        name: GlobalSymbol,
         args: Vec<(Binding<'a>, Type)>,
         return_type: Type,
-        body: Expr<'a>,
+        body: &'a Expr<'a>,
+    },
+    Infix {
+        // `infixl 6 <+> = add`, where the function is a proc or a closed expression
+        operator: Ident,
+        fixity: Fixity,
+        function: &'a Expr<'a>,
     },
+    Record {
+        // `record Point { x: i32, y: i32 }`
+        name: Ident,
+        fields: Vec<(Ident, Type)>,
+    },
+}
+
+/// Which way the operands of operators with the same precedence are grouped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Associativity {
+    Left,  // a - b - c is (a - b) - c
+    Right, // a ++ b ++ c is a ++ (b ++ c)
+    None,  // a < b < c is an error
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fixity {
+    pub precedence: u8, // operators with higher ones group first
+    pub associativity: Associativity,
+}
+
+/// An infix operator, juxtaposition being the one without a symbol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operator {
+    Juxtaposition,
+    Symbol(Ident),
+}
+
+impl std::fmt::Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Juxtaposition => write!(f, " "),
+            Self::Symbol(symbol) => write!(f, " {symbol} "),
+        }
+    }
 }
 
 impl std::fmt::Display for Top<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             Self::Procedure {
+                doc,
                 name,
                 args,
                 return_type,
                 body,
             } => {
+                for line in doc {
+                    if line.is_empty() {
+                        writeln!(f, "///")?;
+                    } else {
+                        writeln!(f, "/// {line}")?;
+                    }
+                }
                 write!(f, "proc {name}(")?;
                 for (idx, (binding, typ)) in args.iter().enumerate() {
                     write!(f, "{binding}:{typ}")?;
@@ -65,45 +212,349 @@ impl std::fmt::Display for Top<'_> {
                 }
                 write!(f, ") -> {return_type} {{\n{body:indent$}\n}}", indent = 4)
             }
+            Self::Infix {
+                operator,
+                fixity,
+                function,
+            } => {
+                let keyword = match fixity.associativity {
+                    Associativity::Left => "infixl",
+                    Associativity::Right => "infixr",
+                    Associativity::None => "infix",
+                };
+                write!(f, "{keyword} {} {operator} = {function}", fixity.precedence)
+            }
+            Self::Record { name, fields } => {
+                write!(f, "record {name} {{ ")?;
+                for (idx, (field, typ)) in fields.iter().enumerate() {
+                    write!(f, "{field}: {typ}")?;
+                    if idx < fields.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, " }}")
+            }
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Which side of its body a binding was written on, so it's printed the same way
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Notation {
+    #[default]
+    Prefix, // x -> body, let x = value in body, name!(a, b)
+    Postfix, // body <- x, body where x = value, a.name!(b)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Expr<'a> {
-    Number(i32),
+    Number(Number<'a>),
+    Bool(bool),
+    Str(&'a str),
+    Char(char),
+    Tuple(&'a [Self]),
+    List(&'a [Self]),
+    Index {
+        // list.[index]
+        list: &'a Self,
+        index: &'a Self,
+    },
+    Record(&'a [(Ident, &'a Self)]), // {x = 1, y = 2}
+    Field {
+        // record.field
+        record: &'a Self,
+        field: Ident,
+    },
+    Update {
+        // {record with x = 3}, a copy with other values for some fields
+        record: &'a Self,
+        fields: &'a [(Ident, &'a Self)],
+    },
     LambdaFunction {
-        arg: Binding<'a>,
-        body: Box<Self>,
+        args: &'a [Binding<'a>], // `x y -> body` is `x -> y -> body`
+        body: &'a Self,
+        notation: Notation,
     },
     LetBinding {
         name: Binding<'a>,
-        value: Box<Self>,
-        body: Box<Self>,
+        value: &'a Self,
+        body: &'a Self,
+        notation: Notation,
+    },
+    LetRec {
+        bindings: &'a [(Binding<'a>, &'a Self)], // each value can refer to all of them
+        body: &'a Self,
+    },
+    BinaryOperation(&'a Self, BinaryOpKind, &'a Self),
+    Infix {
+        // grouped by the fixities of the operators when lowering
+        operands: &'a [Self],
+        operators: &'a [Operator], // between the operands
     },
-    BinaryOperation(Box<Self>, BinaryOpKind, Box<Self>),
-    Referal(Ident<'a>),
+    Section {
+        // `(+ 1)`, `(1 +)` or `(+)`, a function of the missing operands
+        operator: Ident,
+        lhs: Option<&'a Self>,
+        rhs: Option<&'a Self>, // not both
+    },
+    Negation(&'a Self),
+    Referal(Ident), // a proc if no binding has that name
     ProcCall {
-        name: GlobalSymbol<'a>,
-        args: Vec<Self>,
+        name: GlobalSymbol,
+        args: &'a [Self], // the receiver of a method call is the first one
+        notation: Notation,
+    },
+    If {
+        condition: &'a Self,
+        then: &'a Self,
+        otherwise: &'a Self,
     },
 }
 
+impl Expr<'_> {
+    /// Whether its body extends as far to the right as possible, like `x -> body`
+    fn is_open_right(&self) -> bool {
+        matches!(
+            self,
+            Self::LambdaFunction {
+                notation: Notation::Prefix,
+                ..
+            } | Self::LetBinding {
+                notation: Notation::Prefix,
+                ..
+            } | Self::LetRec { .. }
+                | Self::If { .. }
+        )
+    }
+
+    /// Whether it has to be in parentheses to be an operand
+    fn is_compound(&self) -> bool {
+        self.is_open_right()
+            || self.is_open_left()
+            || matches!(self, Self::Infix { .. } | Self::BinaryOperation(..))
+    }
+
+    /// Whether its body extends as far to the left as possible, like `body <- x`
+    fn is_open_left(&self) -> bool {
+        matches!(
+            self,
+            Self::LambdaFunction {
+                notation: Notation::Postfix,
+                ..
+            } | Self::LetBinding {
+                notation: Notation::Postfix,
+                ..
+            }
+        )
+    }
+}
+
+/// An argument of a lambda, where a tuple is in parentheses again so it isn't several arguments
+struct LambdaArg<'b, 'a>(&'b Binding<'a>);
+
+impl std::fmt::Display for LambdaArg<'_, '_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.0 {
+            Binding::Tuple(_) => write!(f, "({})", self.0),
+            arg => write!(f, "{arg}"),
+        }
+    }
+}
+
 impl std::fmt::Display for Expr<'_> {
+    // traced terms get as deep as the calls
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        stacker::maybe_grow(256 * 1024, 4 * 1024 * 1024, || self.fmt_now(f))
+    }
+}
+
+impl Expr<'_> {
+    fn fmt_now(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let indent = f.width().unwrap_or(0);
         write!(f, "{:indent$}", "")?;
         match self {
             Expr::Number(n) => write!(f, "{n}"),
-            Expr::LambdaFunction { arg, body } => write!(f, "{arg} -> {body}"),
+            // quoted and escaped the way the parser reads them
+            Expr::Str(s) => write!(f, "{}", Quoted(s, '"')),
+            Expr::Char(c) => write!(f, "{}", Quoted(c.encode_utf8(&mut [0; 4]), '\'')),
+            Expr::Bool(b) => write!(f, "{b}"),
+            Expr::Tuple(elements) => {
+                write!(f, "(")?;
+                for (idx, element) in elements.iter().enumerate() {
+                    write!(f, "{element}")?;
+                    if idx < elements.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, ")")
+            }
+            Expr::List(elements) => {
+                write!(f, "[")?;
+                for (idx, element) in elements.iter().enumerate() {
+                    write!(f, "{element}")?;
+                    if idx < elements.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, "]")
+            }
+            // like the receiver of a method call
+            Expr::Index { list, index }
+                if list.is_compound() || matches!(list, Expr::Negation(_)) =>
+            {
+                write!(f, "({list}).[{index}]")
+            }
+            Expr::Index { list, index } => write!(f, "{list}.[{index}]"),
+            Expr::Record(fields) => {
+                write!(f, "{{")?;
+                for (idx, (field, value)) in fields.iter().enumerate() {
+                    write!(f, "{field} = {value}")?;
+                    if idx < fields.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, "}}")
+            }
+            Expr::Field { record, field }
+                if record.is_compound() || matches!(record, Expr::Negation(_)) =>
+            {
+                write!(f, "({record}).{field}")
+            }
+            Expr::Field { record, field } => write!(f, "{record}.{field}"),
+            Expr::Update { record, fields } => {
+                write!(f, "{{{record} with ")?;
+                for (idx, (field, value)) in fields.iter().enumerate() {
+                    write!(f, "{field} = {value}")?;
+                    if idx < fields.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, "}}")
+            }
+            Expr::LambdaFunction {
+                args,
+                body,
+                notation: Notation::Prefix,
+            } => {
+                for arg in *args {
+                    write!(f, "{} ", LambdaArg(arg))?;
+                }
+                write!(f, "-> {body}")
+            }
+            Expr::LambdaFunction {
+                args,
+                body,
+                notation: Notation::Postfix,
+            } => {
+                if body.is_open_right() {
+                    write!(f, "({body}) <-")?;
+                } else {
+                    write!(f, "{body} <-")?;
+                }
+                for arg in *args {
+                    write!(f, " {}", LambdaArg(arg))?;
+                }
+                Ok(())
+            }
             Expr::LetBinding {
                 name,
                 value,
                 body: scope,
+                notation: Notation::Prefix,
             } => write!(f, "let {name} = {value} in\n{scope:indent$}",),
-            Expr::BinaryOperation(lhs, kind, rhs) => write!(f, "({lhs}{kind}{rhs})"),
+            Expr::LetBinding {
+                name,
+                value,
+                body,
+                notation: Notation::Postfix,
+            } => {
+                if body.is_open_right() {
+                    write!(f, "({body})")?;
+                } else {
+                    write!(f, "{body}")?;
+                }
+                // the value is an operand, like in an application
+                if value.is_open_right() || value.is_open_left() {
+                    write!(f, " where {name} = ({value})")
+                } else {
+                    write!(f, " where {name} = {value}")
+                }
+            }
+            Expr::LetRec { bindings, body } => {
+                write!(f, "let rec ")?;
+                for (idx, (name, value)) in bindings.iter().enumerate() {
+                    write!(f, "{name} = {value}")?;
+                    if idx < bindings.len() - 1 {
+                        write!(f, " and ")?;
+                    }
+                }
+                write!(f, " in\n{body:indent$}")
+            }
+            Expr::BinaryOperation(lhs, kind, rhs) => {
+                // an operand is in parentheses if it wouldn't group first
+                let fixity = kind.fixity();
+                let looser = |operand: &Expr, side| match operand {
+                    Expr::BinaryOperation(_, inner, _) => {
+                        let inner = inner.fixity();
+                        inner.precedence < fixity.precedence
+                            || inner.precedence == fixity.precedence && fixity.associativity != side
+                    }
+                    _ => operand.is_compound(),
+                };
+                if looser(lhs, Associativity::Left) {
+                    write!(f, "({lhs})")?;
+                } else {
+                    write!(f, "{lhs}")?;
+                }
+                write!(f, "{kind}")?;
+                // `f x -> body` would be a function of two arguments
+                if looser(rhs, Associativity::Right) || matches!(rhs, Expr::LambdaFunction { .. }) {
+                    write!(f, "({rhs})")
+                } else {
+                    write!(f, "{rhs}")
+                }
+            }
+            Expr::Infix {
+                operands,
+                operators,
+            } => {
+                for (idx, operand) in operands.iter().enumerate() {
+                    if operand.is_compound() {
+                        write!(f, "({operand})")?;
+                    } else {
+                        write!(f, "{operand}")?;
+                    }
+                    if let Some(operator) = operators.get(idx) {
+                        write!(f, "{operator}")?;
+                    }
+                }
+                Ok(())
+            }
+            Expr::Section { operator, lhs, rhs } => match (lhs, rhs) {
+                (Some(lhs), _) if lhs.is_open_right() => write!(f, "(({lhs}) {operator})"),
+                (Some(lhs), _) => write!(f, "({lhs} {operator})"),
+                (None, Some(rhs)) => write!(f, "({operator} {rhs})"),
+                (None, None) => write!(f, "({operator})"),
+            },
+            Expr::Negation(expr) if expr.is_compound() => write!(f, "-({expr})"),
+            Expr::Negation(expr) => write!(f, "-{expr}"),
             Expr::Referal(name) => write!(f, "{name}"),
-            Expr::ProcCall { name, args } => {
+            Expr::ProcCall {
+                name,
+                args,
+                notation,
+            } => {
+                let args = match (notation, args) {
+                    (Notation::Postfix, [receiver, args @ ..]) => {
+                        if receiver.is_compound() || matches!(receiver, Expr::Negation(_)) {
+                            write!(f, "({receiver}).")?;
+                        } else {
+                            write!(f, "{receiver}.")?;
+                        }
+                        args
+                    }
+                    _ => args,
+                };
                 write!(f, "{name}!(")?;
                 for (idx, arg) in args.iter().enumerate() {
                     write!(f, "{arg}")?;
@@ -113,16 +564,55 @@ impl std::fmt::Display for Expr<'_> {
                 }
                 write!(f, ")")
             }
+            Expr::If {
+                condition,
+                then,
+                otherwise,
+            } => write!(f, "if {condition} then {then} else {otherwise}"),
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum BinaryOpKind {
-    Call,           // a b or b>a
+    Call,           // a b or b |> a
     Addition,       // a + b
+    Subtraction,    // a - b
     Multiplication, // a * b
+    Division,       // a / b
+    Remainder,      // a % b
     Composition,    // a & b
+    Concatenation,  // a ++ b
+    Equal,          // a == b
+    NotEqual,       // a != b
+    Less,           // a < b
+    LessEqual,      // a <= b
+    Greater,        // a > b
+    GreaterEqual,   // a >= b
+}
+
+impl BinaryOpKind {
+    /// How it groups when it isn't redeclared
+    pub fn fixity(self) -> Fixity {
+        let (precedence, associativity) = match self {
+            Self::Multiplication | Self::Division | Self::Remainder => (7, Associativity::Left),
+            Self::Addition | Self::Subtraction => (6, Associativity::Left),
+            Self::Concatenation => (5, Associativity::Right),
+            Self::Equal
+            | Self::NotEqual
+            | Self::Less
+            | Self::LessEqual
+            | Self::Greater
+            | Self::GreaterEqual => (4, Associativity::None),
+            Self::Composition => (3, Associativity::Left),
+            // it binds the loosest, so `f x + 1` is f (x + 1)
+            Self::Call => (1, Associativity::Left),
+        };
+        Fixity {
+            precedence,
+            associativity,
+        }
+    }
 }
 
 impl std::fmt::Display for BinaryOpKind {
@@ -130,8 +620,18 @@ impl std::fmt::Display for BinaryOpKind {
         match self {
             Self::Call => write!(f, " "),
             Self::Addition => write!(f, " + "),
+            Self::Subtraction => write!(f, " - "),
             Self::Multiplication => write!(f, " * "),
+            Self::Division => write!(f, " / "),
+            Self::Remainder => write!(f, " % "),
             Self::Composition => write!(f, " & "),
+            Self::Concatenation => write!(f, " ++ "),
+            Self::Equal => write!(f, " == "),
+            Self::NotEqual => write!(f, " != "),
+            Self::Less => write!(f, " < "),
+            Self::LessEqual => write!(f, " <= "),
+            Self::Greater => write!(f, " > "),
+            Self::GreaterEqual => write!(f, " >= "),
         }
     }
 }
@@ -1,63 +1,319 @@
 //! This level gives a unique scope for every binding
 //! and captures them for lambda functions
-//! It also desugars composition binops and let bindings
+//! It also groups infix operators by their fixities,
+//! and desugars composition binops, sections, negation, record updates and let bindings,
+//! but not recursive ones, as their functions capture each other.
+//! Every field belongs to a single record, so its position is known
+//! A lambda or proc argument that's a pattern other than a name
+//! becomes a fresh unnamed binding, destructured at the start of the body
 mod keyed_stack;
-use super::level0;
-use crate::common::{Ident, Scope};
+use super::{Arenas, level0};
+use crate::common::{Ident, Quoted, Scope};
 use keyed_stack::KeyedStack;
 use std::collections::{HashMap, HashSet};
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Clone)]
 pub struct State<'a> {
-    bindings: KeyedStack<Ident<'a>, Binding>, // this is a stack
+    arenas: &'a Arenas<'a>,
+    bindings: KeyedStack<Ident, Binding>, // this is a stack
     // with the most global bindings at the bottom.
     // It's keyed because most of the time, Idents are diffrent
     captures: Vec<(usize, HashSet<Binding>)>, // for a binding, whose idx < captures[_].0,
     // its scope should be put in the captures[_].1,.
     // should be sorted by the .0
-    globals: HashMap<GlobalSymbol<'a>, Top<'a>>, // isn't captured
+    globals: HashMap<GlobalSymbol, &'a Top<'a>>, // isn't captured
+    operators: HashMap<level0::Operator, (level0::Fixity, Meaning)>,
+    records: HashMap<Ident, Vec<Ident>>, // the fields of every record, in order
+    fields: HashMap<Ident, Ident>,       // the record declaring every field
+    pub names: HashMap<Scope, Ident>,    // the source names of the bindings, for printing
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// What an infix operator stands for
+#[derive(Debug, Clone, Copy)]
+enum Meaning {
+    Builtin(level0::BinaryOpKind),
+    Pipe,                   // a |> f is f a
+    Proc(GlobalSymbol),     // a op b is op!(a, b)
+    Function(GlobalSymbol), // a op b is (op!() a) b, the proc returning the function
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum BinaryOpKind {
     Call,           // a b
     Addition,       // a + b
+    Subtraction,    // a - b
     Multiplication, // a * b
+    Division,       // a / b
+    Remainder,      // a % b
+    Concatenation,  // a ++ b
+    Equal,          // a == b
+    NotEqual,       // a != b
+    Less,           // a < b
+    LessEqual,      // a <= b
+    Greater,        // a > b
+    GreaterEqual,   // a >= b
+}
+
+impl BinaryOpKind {
+    /// The result of comparing two numbers, if it's a comparison
+    pub fn compare(self, x: i32, y: i32) -> Option<bool> {
+        match self {
+            Self::Equal => Some(x == y),
+            Self::NotEqual => Some(x != y),
+            Self::Less => Some(x < y),
+            Self::LessEqual => Some(x <= y),
+            Self::Greater => Some(x > y),
+            Self::GreaterEqual => Some(x >= y),
+            Self::Call
+            | Self::Addition
+            | Self::Subtraction
+            | Self::Multiplication
+            | Self::Division
+            | Self::Remainder
+            | Self::Concatenation => None,
+        }
+    }
+}
+
+impl From<BinaryOpKind> for level0::BinaryOpKind {
+    fn from(kind: BinaryOpKind) -> Self {
+        match kind {
+            BinaryOpKind::Call => Self::Call,
+            BinaryOpKind::Addition => Self::Addition,
+            BinaryOpKind::Subtraction => Self::Subtraction,
+            BinaryOpKind::Multiplication => Self::Multiplication,
+            BinaryOpKind::Division => Self::Division,
+            BinaryOpKind::Remainder => Self::Remainder,
+            BinaryOpKind::Concatenation => Self::Concatenation,
+            BinaryOpKind::Equal => Self::Equal,
+            BinaryOpKind::NotEqual => Self::NotEqual,
+            BinaryOpKind::Less => Self::Less,
+            BinaryOpKind::LessEqual => Self::LessEqual,
+            BinaryOpKind::Greater => Self::Greater,
+            BinaryOpKind::GreaterEqual => Self::GreaterEqual,
+        }
+    }
 }
 
 impl std::fmt::Display for BinaryOpKind {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", level0::BinaryOpKind::from(*self))
+    }
+}
+
+/// The procs every program has, unless it defines its own with the same name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Builtin {
+    Length, // len!(xs), the number of chars of a string or elements of a list
+    Index,  // xs.[i], which isn't called by name
+    Map,    // map!(xs, f), the list of f applied to every element
+    Fold,   // fold!(xs, init, f), f (f init x0) x1 and so on
+    Push,   // push!(xs, x), the list with x after the elements
+}
+
+impl Builtin {
+    fn named(name: GlobalSymbol) -> Option<Self> {
+        match name.0.as_str() {
+            "len" => Some(Self::Length),
+            "map" => Some(Self::Map),
+            "fold" => Some(Self::Fold),
+            "push" => Some(Self::Push),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> GlobalSymbol {
+        level0::GlobalSymbol(Ident::new(match self {
+            Self::Length => "len",
+            Self::Index => "index",
+            Self::Map => "map",
+            Self::Fold => "fold",
+            Self::Push => "push",
+        }))
+    }
+
+    /// The names of its parameters, for printing its partial calls
+    fn params(self) -> &'static [&'static str] {
         match self {
-            Self::Call => write!(f, " "),
-            Self::Addition => write!(f, " + "),
-            Self::Multiplication => write!(f, " * "),
+            Self::Length => &["xs"],
+            Self::Index => &["xs", "i"],
+            Self::Map => &["xs", "f"],
+            Self::Fold => &["xs", "init", "f"],
+            Self::Push => &["xs", "x"],
+        }
+    }
+
+    /// The source expression calling it with the arguments
+    pub fn resugar<'a>(self, args: &'a [level0::Expr<'a>]) -> level0::Expr<'a> {
+        match (self, args) {
+            (Self::Index, [list, index]) => level0::Expr::Index { list, index },
+            _ => level0::Expr::ProcCall {
+                name: self.name(),
+                args,
+                notation: level0::Notation::Prefix,
+            },
         }
     }
 }
 
+impl std::fmt::Display for Builtin {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// What a call by name runs
+#[derive(Debug, Clone, Copy)]
+enum Callee {
+    Proc(GlobalSymbol),
+    Builtin(Builtin),
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Expr<'a> {
     Number(i32),
+    Bool(bool),
+    Str(&'a str),
+    Char(char),
+    Tuple(Vec<&'a Self>),
+    List(Vec<&'a Self>),
+    Record(Vec<(Ident, &'a Self)>), // in the order the record declares them
+    Field {
+        record: &'a Self,
+        field: Ident,
+        idx: usize, // its position in the record
+    },
     LambdaFunction {
         arg: Binding,
-        body: Box<Self>,
+        body: &'a Self,
         captured: HashSet<Binding>,
     },
-    BinaryOperation(Box<Self>, BinaryOpKind, Box<Self>),
+    LetRec {
+        bindings: Vec<(Binding, &'a Self)>, // the values are lambdas
+        body: &'a Self,
+    },
+    BinaryOperation(&'a Self, BinaryOpKind, &'a Self),
     Referal {
         scope: Scope,
     },
     ProcCall {
-        name: GlobalSymbol<'a>,
-        args: Vec<Self>,
+        name: GlobalSymbol,
+        args: Vec<&'a Self>,
+    },
+    Builtin {
+        builtin: Builtin,
+        args: Vec<&'a Self>, // as many as it takes
+    },
+    If {
+        condition: &'a Self,
+        then: &'a Self,
+        otherwise: &'a Self,
     },
+    Destructure {
+        // matches the value against the pattern, binding its names in the body
+        value: &'a Self,
+        pattern: Pattern,
+        body: &'a Self,
+    },
+}
+
+/// A pattern whose names got their bindings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    Binding(Binding),
+    Wildcard,
+    Number(i32),
+    Bool(bool),
+    Tuple(Vec<Self>),
+}
+
+impl Pattern {
+    /// The bindings it introduces, from left to right
+    pub fn bindings(&self) -> Vec<Binding> {
+        let mut found = vec![];
+        self.collect_bindings(&mut found);
+        found
+    }
+
+    fn collect_bindings(&self, found: &mut Vec<Binding>) {
+        match self {
+            Self::Binding(binding) => found.push(*binding),
+            Self::Wildcard | Self::Number(_) | Self::Bool(_) => {}
+            Self::Tuple(elements) => elements.iter().for_each(|e| e.collect_bindings(found)),
+        }
+    }
+}
+
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Binding(binding) => write!(f, "{binding}"),
+            Self::Wildcard => write!(f, "_"),
+            Self::Number(n) => write!(f, "{n}"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Tuple(elements) => {
+                write!(f, "(")?;
+                for (idx, element) in elements.iter().enumerate() {
+                    write!(f, "{element}")?;
+                    if idx < elements.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, ")")
+            }
+        }
+    }
 }
 
 impl std::fmt::Display for Expr<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             Self::Number(n) => write!(f, "{n}"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Str(s) => write!(f, "{}", Quoted(s, '"')),
+            Self::Char(c) => write!(f, "{}", Quoted(c.encode_utf8(&mut [0; 4]), '\'')),
+            Self::Tuple(elements) => {
+                write!(f, "(")?;
+                for (idx, element) in elements.iter().enumerate() {
+                    write!(f, "{element}")?;
+                    if idx < elements.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, ")")
+            }
+            Self::List(elements) => {
+                write!(f, "[")?;
+                for (idx, element) in elements.iter().enumerate() {
+                    write!(f, "{element}")?;
+                    if idx < elements.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, "]")
+            }
+            Self::Record(fields) => {
+                write!(f, "{{")?;
+                for (idx, (field, value)) in fields.iter().enumerate() {
+                    write!(f, "{field} = {value}")?;
+                    if idx < fields.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, "}}")
+            }
+            Self::Field { record, field, .. } => write!(f, "({record}.{field})"),
             Self::LambdaFunction { arg, body, .. } => write!(f, "{arg} -> {body}"),
+            Self::LetRec { bindings, body } => {
+                write!(f, "(let rec ")?;
+                for (idx, (binding, value)) in bindings.iter().enumerate() {
+                    write!(f, "{binding} = {value}")?;
+                    if idx < bindings.len() - 1 {
+                        write!(f, " and ")?;
+                    }
+                }
+                write!(f, " in {body})")
+            }
             Self::BinaryOperation(lhs, kind, rhs) => write!(f, "({lhs}{kind}{rhs})"),
             Self::Referal { scope } => write!(f, "{scope}"),
             Self::ProcCall { name, args } => {
@@ -70,12 +326,36 @@ impl std::fmt::Display for Expr<'_> {
                 }
                 write!(f, ")")
             }
+            Self::Builtin {
+                builtin: Builtin::Index,
+                args,
+            } => write!(f, "({}.[{}])", args[0], args[1]),
+            Self::Builtin { builtin, args } => {
+                write!(f, "{builtin}!(")?;
+                for (idx, arg) in args.iter().enumerate() {
+                    write!(f, "{arg}")?;
+                    if idx < args.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, ")")
+            }
+            Self::If {
+                condition,
+                then,
+                otherwise,
+            } => write!(f, "(if {condition} then {then} else {otherwise})"),
+            Self::Destructure {
+                value,
+                pattern,
+                body,
+            } => write!(f, "(let {pattern} = {value} in {body})"),
         }
     }
 }
 
 type Type = level0::Type;
-type GlobalSymbol<'a> = level0::GlobalSymbol<'a>;
+type GlobalSymbol = level0::GlobalSymbol;
 
 #[derive(Debug, Clone, Copy, Eq)]
 pub struct Binding {
@@ -103,113 +383,556 @@ impl PartialEq for Binding {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Top<'a> {
     Procedure {
-        name: GlobalSymbol<'a>,
+        name: GlobalSymbol,
         args: Vec<(Binding, Type)>,
         return_type: Type,
-        body: Expr<'a>,
+        body: &'a Expr<'a>,
     },
 }
 
+impl std::fmt::Display for Top<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Procedure {
+                name,
+                args,
+                return_type,
+                body,
+            } => {
+                write!(f, "proc {name}(")?;
+                for (idx, (binding, typ)) in args.iter().enumerate() {
+                    write!(f, "{binding}:{typ}")?;
+                    if idx < args.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, ") -> {return_type} {{\n    {body}\n}}")
+            }
+        }
+    }
+}
+
 impl<'a> State<'a> {
-    fn introduce_new_binding_in<T>(
+    pub fn new(arenas: &'a Arenas<'a>) -> Self {
+        use level0::{Associativity, BinaryOpKind as Kind, Fixity, Operator};
+        let builtin = |kind: Kind| (kind.fixity(), Meaning::Builtin(kind));
+        let builtins = [
+            ("*", builtin(Kind::Multiplication)),
+            ("/", builtin(Kind::Division)),
+            ("%", builtin(Kind::Remainder)),
+            ("+", builtin(Kind::Addition)),
+            ("-", builtin(Kind::Subtraction)),
+            ("++", builtin(Kind::Concatenation)),
+            ("==", builtin(Kind::Equal)),
+            ("!=", builtin(Kind::NotEqual)),
+            ("<", builtin(Kind::Less)),
+            ("<=", builtin(Kind::LessEqual)),
+            (">", builtin(Kind::Greater)),
+            (">=", builtin(Kind::GreaterEqual)),
+            ("&", builtin(Kind::Composition)),
+            (
+                "|>",
+                (
+                    Fixity {
+                        precedence: 2,
+                        associativity: Associativity::Left,
+                    },
+                    Meaning::Pipe,
+                ),
+            ),
+        ];
+        let mut operators = builtins
+            .into_iter()
+            .map(|(symbol, operator)| (Operator::Symbol(Ident::new(symbol)), operator))
+            .collect::<HashMap<_, _>>();
+        operators.insert(Operator::Juxtaposition, builtin(Kind::Call));
+        Self {
+            arenas,
+            bindings: KeyedStack::default(),
+            captures: vec![],
+            globals: HashMap::new(),
+            operators,
+            records: HashMap::new(),
+            fields: HashMap::new(),
+            names: HashMap::new(),
+        }
+    }
+
+    fn alloc(&self, expr: Expr<'a>) -> &'a Expr<'a> {
+        self.arenas.level1.alloc(expr)
+    }
+
+    /// Gives every name of the pattern a new binding
+    fn push_pattern(&mut self, b: level0::Binding, names: &mut Vec<Ident>) -> Pattern {
+        match b {
+            level0::Binding::Name(name) => {
+                if names.contains(&name) {
+                    panic!("{name} is bound more than once in the same pattern") // TODO: report it properly
+                }
+                names.push(name);
+                let scope = Scope::new();
+                self.names.insert(scope, name);
+                self.bindings.push(name, Binding { scope });
+                Pattern::Binding(Binding { scope })
+            }
+            level0::Binding::Wildcard => Pattern::Wildcard,
+            level0::Binding::Number(n) => Pattern::Number(n.value),
+            level0::Binding::Bool(b) => Pattern::Bool(b),
+            level0::Binding::Tuple(elements) => Pattern::Tuple(
+                elements
+                    .iter()
+                    .map(|element| self.push_pattern(*element, names))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn introduce_new_patterns_in<T, X>(
         &mut self,
-        b: level0::Binding<'a>,
+        b: Vec<(level0::Binding, X)>,
         f: impl FnOnce(&mut Self) -> T,
-    ) -> (T, Binding) {
-        self.bindings.push(
-            b.0,
-            Binding {
-                scope: Scope::new(),
-            },
-        );
-        (f(self), self.bindings.pop(&b.0).unwrap())
+    ) -> (T, Vec<(Pattern, X)>) {
+        let mut names = vec![];
+        let patterns = b
+            .into_iter()
+            .map(|(b, x)| (self.push_pattern(b, &mut names), x))
+            .collect();
+        let res = f(self);
+        for name in names {
+            self.bindings.pop(&name);
+        }
+        (res, patterns)
     }
-    fn introduce_new_bindings_in<T, X>(
+
+    fn introduce_new_pattern_in<T>(
         &mut self,
-        b: Vec<(level0::Binding<'a>, X)>,
+        b: level0::Binding,
         f: impl FnOnce(&mut Self) -> T,
-    ) -> (T, impl Iterator<Item = (Binding, X)>) {
-        for b in &b {
-            self.bindings.push(
-                b.0.0,
-                Binding {
-                    scope: Scope::new(),
-                },
+    ) -> (T, Pattern) {
+        if b.is_refutable() {
+            eprintln!(
+                "warning: the pattern {b} is refutable, so the program stops if it doesn't match"
             );
         }
-        (
-            f(self),
-            b.into_iter()
-                .map(|(b, x)| (self.bindings.pop(&b.0).unwrap(), x)),
-        )
+        let (res, mut patterns) = self.introduce_new_patterns_in(vec![(b, ())], f);
+        (res, patterns.pop().unwrap().0)
+    }
+
+    /// The argument taking the pattern apart in the body, a fresh one unless it's a name
+    fn argument(&self, pattern: Pattern, body: &'a Expr<'a>) -> (&'a Expr<'a>, Binding) {
+        match pattern {
+            Pattern::Binding(binding) => (body, binding),
+            pattern => {
+                let scope = Scope::new();
+                let value = self.alloc(Expr::Referal { scope });
+                let body = self.alloc(Expr::Destructure {
+                    value,
+                    pattern,
+                    body,
+                });
+                (body, Binding { scope })
+            }
+        }
     }
 
-    fn construct_a_function_in<'b>(
+    fn construct_a_function_in(
         &mut self,
-        f: impl FnOnce(&mut Self) -> (Expr<'b>, Binding),
-    ) -> Expr<'b> {
+        f: impl FnOnce(&mut Self) -> (&'a Expr<'a>, Binding),
+    ) -> &'a Expr<'a> {
         self.captures.push((self.bindings.len(), HashSet::new()));
         let (body, arg) = f(self);
         let (_, captured) = self.captures.pop().unwrap();
-        Expr::LambdaFunction {
+        self.alloc(Expr::LambdaFunction {
             arg,
-            body: Box::new(body),
+            body,
             captured,
+        })
+    }
+
+    /// `x y -> body` -> `x -> y -> body`, each function capturing what the ones inside it need
+    fn curried_function(
+        &mut self,
+        args: &[level0::Binding],
+        body: &'a level0::Expr<'a>,
+    ) -> &'a Expr<'a> {
+        let [arg, rest @ ..] = args else {
+            return self.map_expr(body);
+        };
+        self.construct_a_function_in(|this| {
+            let (body, pattern) =
+                this.introduce_new_pattern_in(*arg, |this| this.curried_function(rest, body));
+            this.argument(pattern, body)
+        })
+    }
+
+    /// The record declaring the first field, and all its fields,
+    /// checking that it declares the others too
+    fn record_of(
+        &self,
+        fields: &[(Ident, &level0::Expr)],
+        expr: &level0::Expr,
+    ) -> (Ident, Vec<Ident>) {
+        let Some(&record) = self.fields.get(&fields[0].0) else {
+            panic!("no record has the field {}, in {expr}", fields[0].0) // TODO: report it properly
+        };
+        let declared = self.records[&record].clone();
+        for (idx, (field, _)) in fields.iter().enumerate() {
+            if !declared.contains(field) {
+                panic!("{record} has no field {field}, in {expr}") // TODO: report it properly
+            }
+            if fields[..idx].iter().any(|(other, _)| other == field) {
+                panic!("the field {field} is given twice, in {expr}") // TODO: report it properly
+            }
+        }
+        (record, declared)
+    }
+
+    /// What the proc or builtin with that name runs, and the names of its parameters
+    fn callee(&self, name: GlobalSymbol) -> Option<(Callee, Vec<Option<Ident>>)> {
+        if let Some(Top::Procedure { args: params, .. }) = self.globals.get(&name).copied() {
+            let params = params
+                .iter()
+                .map(|(param, _)| self.names.get(&param.scope).copied())
+                .collect();
+            return Some((Callee::Proc(name), params));
+        }
+        let builtin = Builtin::named(name)?;
+        let params = builtin.params().iter().map(|&p| Some(Ident::new(p)));
+        Some((Callee::Builtin(builtin), params.collect()))
+    }
+
+    fn call(&self, callee: Callee, args: Vec<&'a Expr<'a>>) -> &'a Expr<'a> {
+        self.alloc(match callee {
+            Callee::Proc(name) => Expr::ProcCall { name, args },
+            Callee::Builtin(builtin) => Expr::Builtin { builtin, args },
+        })
+    }
+
+    /// `name!(a)` of a proc with two parameters -> `let x = a in y -> name!(x, y)`
+    fn partial_call(
+        &mut self,
+        callee: Callee,
+        args: Vec<&'a Expr<'a>>,
+        params: &[Option<Ident>],
+    ) -> &'a Expr<'a> {
+        let mut fresh = |param: &Option<Ident>| {
+            let scope = Scope::new();
+            if let Some(name) = *param {
+                self.names.insert(scope, name);
+            }
+            Binding { scope }
+        };
+        let passed = params[..args.len()]
+            .iter()
+            .map(&mut fresh)
+            .collect::<Vec<_>>();
+        let missing = params[args.len()..].iter().map(fresh).collect::<Vec<_>>();
+        let all = passed.iter().chain(&missing).copied().collect::<Vec<_>>();
+        let call = self.call(
+            callee,
+            all.iter()
+                .map(|binding| {
+                    self.alloc(Expr::Referal {
+                        scope: binding.scope,
+                    })
+                })
+                .collect(),
+        );
+        // a function captures the bindings before its argument
+        let function =
+            all.iter()
+                .enumerate()
+                .skip(args.len())
+                .rev()
+                .fold(call, |body, (idx, &arg)| {
+                    self.alloc(Expr::LambdaFunction {
+                        arg,
+                        body,
+                        captured: all[..idx].iter().copied().collect(),
+                    })
+                });
+        // the passed arguments are evaluated right away, like in a full call
+        passed
+            .iter()
+            .zip(args)
+            .enumerate()
+            .rev()
+            .fold(function, |body, (idx, (&arg, value))| {
+                let fun = self.alloc(Expr::LambdaFunction {
+                    arg,
+                    body,
+                    captured: passed[..idx].iter().copied().collect(),
+                });
+                self.alloc(Expr::BinaryOperation(fun, BinaryOpKind::Call, value))
+            })
+    }
+
+    /// Groups the operands from `*next` on, while the operators bind tighter than `min`
+    fn group_infix(
+        &self,
+        operands: &'a [level0::Expr<'a>],
+        operators: &[level0::Operator],
+        next: &mut usize,
+        min: u8,
+    ) -> &'a level0::Expr<'a> {
+        let mut lhs = &operands[*next];
+        while let Some(&operator) = operators.get(*next) {
+            let (fixity, meaning) = self.operator(operator);
+            if fixity.precedence < min {
+                break;
+            }
+            *next += 1;
+            let rhs_min = match fixity.associativity {
+                level0::Associativity::Left | level0::Associativity::None => fixity.precedence + 1,
+                level0::Associativity::Right => fixity.precedence,
+            };
+            let rhs = self.group_infix(operands, operators, next, rhs_min);
+            lhs = self.arenas.level0.alloc(match meaning {
+                Meaning::Builtin(kind) => level0::Expr::BinaryOperation(lhs, kind, rhs),
+                Meaning::Pipe => {
+                    level0::Expr::BinaryOperation(rhs, level0::BinaryOpKind::Call, lhs)
+                }
+                Meaning::Proc(name) => level0::Expr::ProcCall {
+                    name,
+                    args: self.arenas.level0.alloc_slice([*lhs, *rhs]),
+                    notation: level0::Notation::Prefix,
+                },
+                Meaning::Function(name) => {
+                    let function = self.arenas.level0.alloc(level0::Expr::ProcCall {
+                        name,
+                        args: &[],
+                        notation: level0::Notation::Prefix,
+                    });
+                    let partial = self.arenas.level0.alloc(level0::Expr::BinaryOperation(
+                        function,
+                        level0::BinaryOpKind::Call,
+                        lhs,
+                    ));
+                    level0::Expr::BinaryOperation(partial, level0::BinaryOpKind::Call, rhs)
+                }
+            });
+            if fixity.associativity == level0::Associativity::None
+                && let Some(&following) = operators.get(*next)
+                && self.operator(following).0.precedence == fixity.precedence
+            {
+                panic!(
+                    "{} and {} don't chain, add parentheses, in {lhs}",
+                    operator.to_string().trim(),
+                    following.to_string().trim()
+                ) // TODO: report it properly
+            }
         }
+        lhs
     }
 
-    pub fn map_expr(&mut self, expr: level0::Expr<'a>) -> Expr<'a> {
-        match expr {
-            level0::Expr::Number(a) => Expr::Number(a),
-            level0::Expr::LambdaFunction { arg, body } => self.construct_a_function_in(|this| {
-                this.introduce_new_binding_in(arg, |this| this.map_expr(*body))
-            }),
-            level0::Expr::LetBinding { name, value, body } => {
+    fn operator(&self, operator: level0::Operator) -> (level0::Fixity, Meaning) {
+        let Some(&found) = self.operators.get(&operator) else {
+            panic!(
+                "that operator ({}) wasn't declared",
+                operator.to_string().trim()
+            ) // TODO: report it properly
+        };
+        found
+    }
+
+    pub fn map_expr(&mut self, expr: &'a level0::Expr<'a>) -> &'a Expr<'a> {
+        match *expr {
+            level0::Expr::Number(a) => self.alloc(Expr::Number(a.value)),
+            level0::Expr::Bool(b) => self.alloc(Expr::Bool(b)),
+            level0::Expr::Str(s) => self.alloc(Expr::Str(s)),
+            level0::Expr::Char(c) => self.alloc(Expr::Char(c)),
+            level0::Expr::Tuple(elements) => {
+                let elements = elements.iter().map(|e| self.map_expr(e)).collect();
+                self.alloc(Expr::Tuple(elements))
+            }
+            level0::Expr::List(elements) => {
+                let elements = elements.iter().map(|e| self.map_expr(e)).collect();
+                self.alloc(Expr::List(elements))
+            }
+            level0::Expr::Record(fields) => {
+                let (record, declared) = self.record_of(fields, expr);
+                let mut lowered = vec![];
+                for field in declared {
+                    let Some(&(_, value)) = fields.iter().find(|(given, _)| *given == field) else {
+                        panic!("the field {field} of {record} is missing, in {expr}") // TODO: report it properly
+                    };
+                    lowered.push((field, self.map_expr(value)));
+                }
+                self.alloc(Expr::Record(lowered))
+            }
+            level0::Expr::Field { record, field } => {
+                let Some(declared) = self.fields.get(&field).map(|record| &self.records[record])
+                else {
+                    panic!("no record has the field {field}, in {expr}") // TODO: report it properly
+                };
+                let idx = declared.iter().position(|&f| f == field).unwrap();
+                let record = self.map_expr(record);
+                self.alloc(Expr::Field { record, field, idx })
+            }
+            level0::Expr::Update { record, fields } => {
+                // {r with x = 3} -> let tmp = r in {x = 3, y = tmp.y}
+                let (_, declared) = self.record_of(fields, expr);
+                let value = self.map_expr(record);
+                let tmp = Binding {
+                    scope: Scope::new(),
+                };
+                let mut lowered = vec![];
+                for (idx, field) in declared.into_iter().enumerate() {
+                    let value = match fields.iter().find(|(given, _)| *given == field) {
+                        Some(&(_, value)) => self.map_expr(value),
+                        None => {
+                            let record = self.alloc(Expr::Referal { scope: tmp.scope });
+                            self.alloc(Expr::Field { record, field, idx })
+                        }
+                    };
+                    lowered.push((field, value));
+                }
+                self.alloc(Expr::Destructure {
+                    value,
+                    pattern: Pattern::Binding(tmp),
+                    body: self.alloc(Expr::Record(lowered)),
+                })
+            }
+            level0::Expr::Index { list, index } => {
+                let args = vec![self.map_expr(list), self.map_expr(index)];
+                self.alloc(Expr::Builtin {
+                    builtin: Builtin::Index,
+                    args,
+                })
+            }
+            level0::Expr::LambdaFunction { args, body, .. } => self.curried_function(args, body),
+            level0::Expr::LetBinding {
+                name, value, body, ..
+            } => {
                 // let name = value in scope -> (|name|body)(value)
-                let value = self.map_expr(*value);
+                let value = self.map_expr(value);
                 let fun = self.construct_a_function_in(|this| {
-                    this.introduce_new_binding_in(name, |this| this.map_expr(*body))
+                    let (body, pattern) =
+                        this.introduce_new_pattern_in(name, |this| this.map_expr(body));
+                    this.argument(pattern, body)
                 });
 
-                Expr::BinaryOperation(Box::new(fun), BinaryOpKind::Call, Box::new(value))
+                self.alloc(Expr::BinaryOperation(fun, BinaryOpKind::Call, value))
+            }
+            level0::Expr::LetRec { bindings, body } => {
+                // all the names are in scope in the values, so the lambdas capture each other
+                let names = bindings.iter().map(|&(name, _)| (name, ())).collect();
+                let ((values, body), bindings) = self.introduce_new_patterns_in(names, |this| {
+                    let values = bindings
+                        .iter()
+                        .map(|(name, value)| match value {
+                            level0::Expr::LambdaFunction { .. } => this.map_expr(value),
+                            _ => panic!("{name} is recursive, so it must be a lambda"), // TODO: report it properly
+                        })
+                        .collect::<Vec<_>>();
+                    (values, this.map_expr(body))
+                });
+                let bindings = bindings
+                    .into_iter()
+                    .map(|(pattern, ())| match pattern {
+                        Pattern::Binding(binding) => binding,
+                        _ => unreachable!("the parser only allows names"),
+                    })
+                    .zip(values)
+                    .collect();
+                self.alloc(Expr::LetRec { bindings, body })
             }
             level0::Expr::BinaryOperation(lhs, kind, rhs) => {
                 macro_rules! simple {
-                    ($op:ident) => {
-                        Expr::BinaryOperation(
-                            Box::new(self.map_expr(*lhs)),
-                            BinaryOpKind::$op,
-                            Box::new(self.map_expr(*rhs)),
-                        )
-                    };
+                    ($op:ident) => {{
+                        let lhs = self.map_expr(lhs);
+                        let rhs = self.map_expr(rhs);
+                        self.alloc(Expr::BinaryOperation(lhs, BinaryOpKind::$op, rhs))
+                    }};
                 }
                 match kind {
                     level0::BinaryOpKind::Call => simple!(Call),
                     level0::BinaryOpKind::Addition => simple!(Addition),
+                    level0::BinaryOpKind::Subtraction => simple!(Subtraction),
                     level0::BinaryOpKind::Multiplication => simple!(Multiplication),
+                    level0::BinaryOpKind::Division => simple!(Division),
+                    level0::BinaryOpKind::Remainder => simple!(Remainder),
+                    level0::BinaryOpKind::Concatenation => simple!(Concatenation),
+                    level0::BinaryOpKind::Equal => simple!(Equal),
+                    level0::BinaryOpKind::NotEqual => simple!(NotEqual),
+                    level0::BinaryOpKind::Less => simple!(Less),
+                    level0::BinaryOpKind::LessEqual => simple!(LessEqual),
+                    level0::BinaryOpKind::Greater => simple!(Greater),
+                    level0::BinaryOpKind::GreaterEqual => simple!(GreaterEqual),
                     level0::BinaryOpKind::Composition => {
                         // a.b -> |scope| a(b(scope))
                         let scope = Scope::new();
                         self.construct_a_function_in(|this| {
+                            let lhs = this.map_expr(lhs);
+                            let rhs = this.map_expr(rhs);
+                            let arg = this.alloc(Expr::Referal { scope });
+                            let inner =
+                                this.alloc(Expr::BinaryOperation(rhs, BinaryOpKind::Call, arg));
                             (
-                                Expr::BinaryOperation(
-                                    Box::new(this.map_expr(*lhs)),
-                                    BinaryOpKind::Call,
-                                    Box::new(Expr::BinaryOperation(
-                                        Box::new(this.map_expr(*rhs)),
-                                        BinaryOpKind::Call,
-                                        Box::new(Expr::Referal { scope }),
-                                    )),
-                                ),
+                                this.alloc(Expr::BinaryOperation(lhs, BinaryOpKind::Call, inner)),
                                 Binding { scope },
                             )
                         })
                     }
                 }
             }
+            level0::Expr::Infix {
+                operands,
+                operators,
+            } => {
+                let grouped = self.group_infix(operands, operators, &mut 0, 0);
+                self.map_expr(grouped)
+            }
+            level0::Expr::Section { operator, lhs, rhs } => {
+                // (+ 1) -> let y = 1 in x -> x + y, so the operand is evaluated right away.
+                // Only the bindings made here are in scope in the function, so they can't clash
+                let level0 = &self.arenas.level0;
+                let (x, y) = (Ident::new("x"), Ident::new("y"));
+                let operation = level0.alloc(level0::Expr::Infix {
+                    operands: level0
+                        .alloc_slice([level0::Expr::Referal(x), level0::Expr::Referal(y)]),
+                    operators: self
+                        .arenas
+                        .operators
+                        .alloc_slice([level0::Operator::Symbol(operator)]),
+                });
+                let (given, missing) = match (lhs, rhs) {
+                    (Some(lhs), None) => (Some((x, lhs)), vec![level0::Binding::Name(y)]),
+                    (None, Some(rhs)) => (Some((y, rhs)), vec![level0::Binding::Name(x)]),
+                    (None, None) => (
+                        None,
+                        vec![level0::Binding::Name(x), level0::Binding::Name(y)],
+                    ),
+                    (Some(_), Some(_)) => unreachable!("that's an infix operation"),
+                };
+                let function = level0.alloc(level0::Expr::LambdaFunction {
+                    args: self.arenas.patterns.alloc_slice(missing),
+                    body: operation,
+                    notation: level0::Notation::Prefix,
+                });
+                self.map_expr(match given {
+                    Some((name, value)) => level0.alloc(level0::Expr::LetBinding {
+                        name: level0::Binding::Name(name),
+                        value,
+                        body: function,
+                        notation: level0::Notation::Prefix,
+                    }),
+                    None => function,
+                })
+            }
+            level0::Expr::Negation(expr) => {
+                // -a -> 0 - a
+                let zero = self.alloc(Expr::Number(0));
+                let expr = self.map_expr(expr);
+                self.alloc(Expr::BinaryOperation(zero, BinaryOpKind::Subtraction, expr))
+            }
 
             level0::Expr::Referal(name) => {
                 let Some((idx, &relevant_binding)) = self.bindings.find(&name) else {
+                    // a proc used as a value is its partial call without arguments
+                    if let Some((callee, params)) = self.callee(level0::GlobalSymbol(name)) {
+                        return self.partial_call(callee, vec![], &params);
+                    }
                     panic!("that binding ({name}) wasn't found") // TODO: report it properly
                 };
                 let first_valid = self
@@ -219,49 +942,144 @@ impl<'a> State<'a> {
                 for (_, set) in self.captures.iter_mut().skip(first_valid) {
                     set.insert(relevant_binding);
                 }
-                Expr::Referal {
+                self.alloc(Expr::Referal {
                     scope: relevant_binding.scope,
-                }
+                })
             }
 
-            level0::Expr::ProcCall { name, args } => {
+            level0::Expr::ProcCall { name, args, .. } => {
                 let args = args
-                    .into_iter()
+                    .iter()
                     .map(|arg| self.map_expr(arg))
                     .collect::<Vec<_>>();
-                let Some(_top) = self.globals.get(&name) else {
-                    panic!("that proc ({name}) wasn't found")
+                // the call is printed the way it was written, so method calls are recognizable
+                let Some((callee, params)) = self.callee(name) else {
+                    panic!("that proc ({name}) wasn't found, in {expr}") // TODO: report it properly
                 };
-                Expr::ProcCall { name, args }
+                match args.len().cmp(&params.len()) {
+                    std::cmp::Ordering::Equal => self.call(callee, args),
+                    std::cmp::Ordering::Less => self.partial_call(callee, args, &params),
+                    std::cmp::Ordering::Greater => panic!(
+                        "{name} takes {} arguments, but got {}, in {expr}",
+                        params.len(),
+                        args.len()
+                    ), // TODO: report it properly
+                }
+            }
+            level0::Expr::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                // both branches capture from the same scope as the condition
+                let condition = self.map_expr(condition);
+                let then = self.map_expr(then);
+                let otherwise = self.map_expr(otherwise);
+                self.alloc(Expr::If {
+                    condition,
+                    then,
+                    otherwise,
+                })
             }
         }
     }
 
-    pub fn map_top(&mut self, top: level0::Top<'a>) -> Top<'a> {
+    /// The procs it defines, which are the procs declared and the functions of declared operators
+    pub fn map_top(&mut self, top: level0::Top<'a>) -> Option<&'a Top<'a>> {
         match top {
             level0::Top::Procedure {
+                doc: _,
                 name,
                 args,
                 return_type,
                 body,
             } => {
-                let (body, args) = self.introduce_new_bindings_in(args, |this| this.map_expr(body));
+                for (arg, _) in &args {
+                    if arg.is_refutable() {
+                        eprintln!(
+                            "warning: the parameter {arg} of {name} is refutable, so the program stops if it doesn't match"
+                        );
+                    }
+                }
+                let (mut body, patterns) =
+                    self.introduce_new_patterns_in(args, |this| this.map_expr(body));
+                // destructured in reverse, so the first parameter is matched first
+                let mut args = vec![];
+                for (pattern, typ) in patterns.into_iter().rev() {
+                    let (destructuring, arg) = self.argument(pattern, body);
+                    body = destructuring;
+                    args.push((arg, typ));
+                }
+                args.reverse();
                 let top = Top::Procedure {
                     name,
-                    args: args.collect(),
+                    args,
                     return_type,
                     body,
                 };
-                self.globals.insert(name, top.clone());
+                let top = self.arenas.tops.alloc(top);
+                self.globals.insert(name, top);
+                Some(top)
+            }
+            level0::Top::Infix {
+                operator,
+                fixity,
+                function,
+            } => {
+                let (meaning, top) = match *function {
+                    level0::Expr::Referal(name)
+                        if self.callee(level0::GlobalSymbol(name)).is_some() =>
+                    {
+                        (Meaning::Proc(level0::GlobalSymbol(name)), None)
+                    }
+                    _ => {
+                        // lowered once, into a proc named by the operator,
+                        // so its names mean what they mean here and not where it's used
+                        let name = level0::GlobalSymbol(operator);
+                        let top = self.arenas.tops.alloc(Top::Procedure {
+                            name,
+                            args: vec![],
+                            return_type: Type::default(),
+                            body: self.map_expr(function),
+                        });
+                        self.globals.insert(name, top);
+                        (Meaning::Function(name), Some(top))
+                    }
+                };
+                self.operators
+                    .insert(level0::Operator::Symbol(operator), (fixity, meaning));
                 top
             }
+            level0::Top::Record { name, fields } => {
+                let fields = fields
+                    .into_iter()
+                    .map(|(field, _)| field)
+                    .collect::<Vec<_>>();
+                // values made before would have their fields elsewhere
+                if self.records.contains_key(&name) {
+                    panic!("the record {name} is already declared") // TODO: report it properly
+                }
+                for (idx, field) in fields.iter().enumerate() {
+                    if fields[..idx].contains(field) {
+                        panic!("the field {field} is declared twice, in record {name}") // TODO: report it properly
+                    }
+                    if let Some(other) = self.fields.get(field) {
+                        panic!("the field {field} of {name} is already declared by {other}") // TODO: report it properly
+                    }
+                }
+                for field in &fields {
+                    self.fields.insert(*field, name);
+                }
+                self.records.insert(name, fields);
+                None
+            }
         }
     }
 
     pub fn ensure_main_exists(&self) {
         assert!(self.bindings.len() == 0);
         assert!(self.captures.is_empty());
-        let Some(_) = self.globals.get(&level0::GlobalSymbol(Ident("main"))) else {
+        let Some(_) = self.globals.get(&level0::GlobalSymbol(Ident::new("main"))) else {
             panic!("main wasn't found")
         };
     }
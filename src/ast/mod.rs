@@ -1,53 +1,155 @@
+mod asm;
 mod eval;
 mod level0;
 mod level1;
+mod optimize;
 mod parser;
+mod trace;
+mod wat;
 
-pub use parser::parser;
+pub use eval::{Error as EvalError, Options, Strategy};
+pub use parser::{parser, repl_parser};
+pub use trace::TraceOptions;
 
-pub struct InitialLevel<'a>(Vec<level0::Top<'a>>);
+use crate::common::{Arena, Ident, Scope};
+use std::collections::HashMap;
+
+/// Where the nodes of all the levels live
+#[derive(Debug, Default)]
+pub struct Arenas<'a> {
+    level0: Arena<level0::Expr<'a>>,
+    patterns: Arena<level0::Binding<'a>>,
+    operators: Arena<level0::Operator>,
+    strings: Arena<String>,
+    types: Arena<level0::Type>,
+    rec_bindings: Arena<(level0::Binding<'a>, &'a level0::Expr<'a>)>,
+    fields: Arena<(Ident, &'a level0::Expr<'a>)>,
+    level1: Arena<level1::Expr<'a>>,
+    tops: Arena<level1::Top<'a>>,
+}
+
+pub struct InitialLevel<'a> {
+    tops: Vec<level0::Top<'a>>,
+    arenas: &'a Arenas<'a>,
+}
 
 impl std::fmt::Display for InitialLevel<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        for top in &self.0 {
+        for top in &self.tops {
             writeln!(f, "{top}")?;
         }
         Ok(())
     }
 }
 
-impl<'a> From<Vec<level0::Top<'a>>> for InitialLevel<'a> {
-    fn from(value: Vec<level0::Top<'a>>) -> Self {
-        Self(value)
-    }
-}
-
 impl<'a> InitialLevel<'a> {
     pub fn lower_all_the_way(self) -> FinalLevel<'a> {
-        let mut state1 = level1::State::default();
+        let mut state1 = level1::State::new(self.arenas);
         let level1 = self
-            .0
+            .tops
             .into_iter()
-            .map(|top| state1.map_top(top))
+            .filter_map(|top| state1.map_top(top))
             .collect::<Vec<_>>();
         state1.ensure_main_exists();
-        FinalLevel(level1)
+        FinalLevel {
+            tops: level1,
+            names: state1.names,
+            arenas: self.arenas,
+        }
     }
 }
 
 #[derive(Debug)]
-pub struct FinalLevel<'a>(Vec<level1::Top<'a>>);
+pub struct FinalLevel<'a> {
+    tops: Vec<&'a level1::Top<'a>>,
+    names: HashMap<Scope, Ident>,
+    arenas: &'a Arenas<'a>,
+}
+
+impl std::fmt::Display for FinalLevel<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for top in &self.tops {
+            writeln!(f, "{top}")?;
+        }
+        Ok(())
+    }
+}
 
-impl FinalLevel<'_> {
-    pub fn eval(self) {
-        let mut state = eval::State::default();
-        for top in self.0 {
+impl<'a> FinalLevel<'a> {
+    pub fn eval(self, options: Options) -> Result<(), EvalError> {
+        let mut state = eval::State::new(options, self.names, self.arenas);
+        for top in self.tops {
             state.eval_top(top);
         }
-        let result = state.eval_expr(level1::Expr::ProcCall {
-            name: level0::GlobalSymbol(crate::common::Ident("main")),
+        let main = self.arenas.level1.alloc(level1::Expr::ProcCall {
+            name: level0::GlobalSymbol(Ident::new("main")),
             args: vec![],
         });
+        eval_and_print(&mut state, main, options)
+    }
+}
+
+fn eval_and_print<'a>(
+    state: &mut eval::State<'a>,
+    expr: &'a level1::Expr<'a>,
+    options: Options,
+) -> Result<(), EvalError> {
+    let result = state.eval_expr(expr)?;
+    let result = state.force_all(result)?;
+    if options.normalize {
+        println!("{}", state.read_back(result)?);
+    } else {
         println!("{result}");
     }
+    Ok(())
+}
+
+/// An input of the REPL
+pub enum Line<'a> {
+    Definitions(Vec<level0::Top<'a>>),
+    Expression(&'a level0::Expr<'a>),
+}
+
+/// Keeps the definitions of every line given to the REPL
+pub struct Session<'a> {
+    arenas: &'a Arenas<'a>,
+    lowering: level1::State<'a>,
+    tops: Vec<&'a level1::Top<'a>>,
+}
+
+impl<'a> Session<'a> {
+    pub fn new(arenas: &'a Arenas<'a>) -> Self {
+        Self {
+            arenas,
+            lowering: level1::State::new(arenas),
+            tops: vec![],
+        }
+    }
+
+    /// Defines the procs or prints the value of the expression.
+    /// The line is lowered on a copy, so the session is kept as it was if lowering panics
+    pub fn run(&mut self, line: Line<'a>, options: Options) -> Result<(), EvalError> {
+        let mut lowering = self.lowering.clone();
+        match line {
+            Line::Definitions(tops) => {
+                let mut lowered = vec![];
+                for top in tops {
+                    lowered.extend(lowering.map_top(top));
+                }
+                self.lowering = lowering;
+                self.tops.extend(lowered);
+                Ok(())
+            }
+            Line::Expression(expr) => {
+                let expr = lowering.map_expr(expr);
+                self.lowering = lowering;
+                let names = self.lowering.names.clone();
+                let mut state = eval::State::new(options, names, self.arenas);
+                for top in &self.tops {
+                    state.eval_top(top);
+                }
+                eval_and_print(&mut state, expr, options)
+            }
+        }
+    }
 }
@@ -0,0 +1,1388 @@
+//! Simplifies the lowered program without changing what it evaluates to:
+//! folds constants, including concatenations and lengths of literals,
+//! and `if`s on known conditions, removes `x + 0`, `x - 0`, `x * 1` and `x / 1`
+//! when `x` is a number and beta-reduces the applied
+//! lambdas that let bindings become, when that doesn't duplicate work
+//! or move it over work that may fail or not stop.
+//! Destructuring a tuple expression binds its elements one by one,
+//! and reading a field of a record expression keeps just that field.
+//! Small and single-use procs are inlined, others get a copy specialized
+//! on the numbers and closed lambdas they're called with.
+//! A function is only copied with fresh bindings, so the scopes stay unique
+use super::level0::{GlobalSymbol, Type};
+use super::level1::{BinaryOpKind, Binding, Builtin, Expr, Pattern, Top};
+use super::{Arenas, FinalLevel};
+use crate::common::{Ident, Scope};
+use std::collections::{HashMap, HashSet};
+
+/// How many times inlining and specializing is followed by simplifying
+const ROUNDS: usize = 4;
+/// The most nodes a proc body can have to be inlined everywhere
+const INLINE_SIZE: usize = 24;
+/// The most specialized copies of procs in a program
+const MAX_SPECIALIZATIONS: usize = 16;
+
+/// How a binding is used in an expression
+#[derive(Debug, Clone, Copy, Default)]
+struct Uses {
+    count: usize,
+    under_lambda: bool, // so it can be evaluated any number of times
+}
+
+/// What's known about a proc at the start of a round
+#[derive(Debug, Clone, Copy)]
+struct ProcInfo {
+    recursive: bool, // calls itself, maybe through other procs
+    call_sites: usize,
+    size: usize,
+}
+
+struct Specialization<'a> {
+    of: GlobalSymbol,
+    known: Vec<Option<&'a Expr<'a>>>, // the known arguments
+    name: GlobalSymbol,
+}
+
+struct Optimizer<'a> {
+    arenas: &'a Arenas<'a>,
+    names: HashMap<Scope, Ident>,
+    tops: Vec<&'a Top<'a>>,
+    procs: HashMap<GlobalSymbol, ProcInfo>, // procs made during a round aren't here
+    specializations: Vec<Specialization<'a>>,
+    changed: bool,
+}
+
+fn count_uses(expr: &Expr, scope: Scope, under_lambda: bool, uses: &mut Uses) {
+    match expr {
+        Expr::Number(_) | Expr::Bool(_) | Expr::Str(_) | Expr::Char(_) => {}
+        Expr::Referal { scope: s } => {
+            if *s == scope {
+                uses.count += 1;
+                uses.under_lambda |= under_lambda;
+            }
+        }
+        // a let binding, its body is evaluated once
+        Expr::BinaryOperation(Expr::LambdaFunction { body, .. }, BinaryOpKind::Call, value) => {
+            count_uses(body, scope, under_lambda, uses);
+            count_uses(value, scope, under_lambda, uses);
+        }
+        Expr::LambdaFunction { body, .. } => count_uses(body, scope, true, uses),
+        Expr::LetRec { bindings, body } => {
+            for (_, value) in bindings {
+                count_uses(value, scope, true, uses);
+            }
+            count_uses(body, scope, under_lambda, uses);
+        }
+        Expr::BinaryOperation(lhs, _, rhs) => {
+            count_uses(lhs, scope, under_lambda, uses);
+            count_uses(rhs, scope, under_lambda, uses);
+        }
+        Expr::ProcCall { args, .. }
+        | Expr::Builtin { args, .. }
+        | Expr::Tuple(args)
+        | Expr::List(args) => {
+            for arg in args {
+                count_uses(arg, scope, under_lambda, uses);
+            }
+        }
+        Expr::Record(fields) => {
+            for (_, value) in fields {
+                count_uses(value, scope, under_lambda, uses);
+            }
+        }
+        Expr::Field { record, .. } => count_uses(record, scope, under_lambda, uses),
+        Expr::Destructure { value, body, .. } => {
+            count_uses(value, scope, under_lambda, uses);
+            count_uses(body, scope, under_lambda, uses);
+        }
+        // a branch may not be evaluated at all, so nothing is moved into it
+        Expr::If {
+            condition,
+            then,
+            otherwise,
+        } => {
+            count_uses(condition, scope, under_lambda, uses);
+            count_uses(then, scope, true, uses);
+            count_uses(otherwise, scope, true, uses);
+        }
+    }
+}
+
+/// Whether evaluating it surely stops without an error, so it can be dropped or moved
+fn is_total(expr: &Expr) -> bool {
+    match expr {
+        Expr::Number(_)
+        | Expr::Bool(_)
+        | Expr::Str(_)
+        | Expr::Char(_)
+        | Expr::Referal { .. }
+        | Expr::LambdaFunction { .. } => true,
+        // the values are lambdas
+        Expr::LetRec { body, .. } => is_total(body),
+        Expr::ProcCall { .. } => false,
+        // only on literals that it's known to work on
+        Expr::BinaryOperation(lhs, kind, rhs) => match (lhs, rhs) {
+            (Expr::Number(x), Expr::Number(y)) => fold(*x, *kind, *y).is_some(),
+            (Expr::Bool(_), Expr::Bool(_)) => {
+                matches!(kind, BinaryOpKind::Equal | BinaryOpKind::NotEqual)
+            }
+            (Expr::Str(_), Expr::Str(_)) => *kind == BinaryOpKind::Concatenation,
+            (Expr::Char(x), Expr::Char(y)) => kind.compare(*x as i32, *y as i32).is_some(),
+            _ => false,
+        },
+        // the others index or call functions
+        Expr::Builtin {
+            builtin: Builtin::Length,
+            args,
+        } => matches!(args[..], [Expr::Str(_) | Expr::List(_)]) && is_total(args[0]),
+        Expr::Builtin {
+            builtin: Builtin::Push,
+            args,
+        } => matches!(args[0], Expr::List(_)) && args.iter().all(|arg| is_total(arg)),
+        Expr::Tuple(args) | Expr::List(args) => args.iter().all(|arg| is_total(arg)),
+        Expr::Builtin { .. } => false,
+        Expr::Record(fields) => fields.iter().all(|(_, value)| is_total(value)),
+        Expr::Field { record, field, .. } => match record {
+            Expr::Record(fields) => {
+                fields.iter().any(|(name, _)| name == field) && is_total(record)
+            }
+            _ => false,
+        },
+        Expr::Destructure {
+            value,
+            pattern,
+            body,
+        } => matches_surely(value, pattern) && is_total(value) && is_total(body),
+        Expr::If {
+            condition,
+            then,
+            otherwise,
+        } => is_total(condition) && is_total(then) && is_total(otherwise),
+    }
+}
+
+/// Whether the value matches the pattern whatever it evaluates to
+fn matches_surely(value: &Expr, pattern: &Pattern) -> bool {
+    match (value, pattern) {
+        (_, Pattern::Binding(_) | Pattern::Wildcard) => true,
+        (Expr::Tuple(elements), Pattern::Tuple(patterns)) => {
+            elements.len() == patterns.len()
+                && elements
+                    .iter()
+                    .zip(patterns)
+                    .all(|(value, pattern)| matches_surely(value, pattern))
+        }
+        _ => false,
+    }
+}
+
+/// Whether what's evaluated before the single use of the binding surely stops without
+/// an error, so the value of the binding can be evaluated there instead of first.
+/// None if it isn't used in the expression
+fn total_before(expr: &Expr, scope: Scope) -> Option<bool> {
+    // evaluated one after the other
+    let sequence = |exprs: &mut dyn Iterator<Item = &Expr>| {
+        let mut total = true;
+        for expr in exprs {
+            match total_before(expr, scope) {
+                Some(before) => return Some(total && before),
+                None => total &= is_total(expr),
+            }
+        }
+        None
+    };
+    match expr {
+        Expr::Referal { scope: s } if *s == scope => Some(true),
+        Expr::Number(_)
+        | Expr::Bool(_)
+        | Expr::Str(_)
+        | Expr::Char(_)
+        | Expr::Referal { .. }
+        | Expr::LambdaFunction { .. } => None,
+        // a let binding, its value is evaluated before its body
+        Expr::BinaryOperation(Expr::LambdaFunction { body, .. }, BinaryOpKind::Call, value) => {
+            sequence(&mut [*value, *body].into_iter())
+        }
+        Expr::BinaryOperation(lhs, _, rhs) => sequence(&mut [*lhs, *rhs].into_iter()),
+        Expr::LetRec { body, .. } => total_before(body, scope),
+        Expr::ProcCall { args, .. }
+        | Expr::Builtin { args, .. }
+        | Expr::Tuple(args)
+        | Expr::List(args) => sequence(&mut args.iter().copied()),
+        Expr::Record(fields) => sequence(&mut fields.iter().map(|(_, value)| *value)),
+        Expr::Field { record, .. } => total_before(record, scope),
+        // the branches are evaluated after the condition
+        Expr::If { condition, .. } => total_before(condition, scope),
+        Expr::Destructure {
+            value,
+            pattern,
+            body,
+        } => total_before(value, scope).or_else(|| {
+            let total = matches_surely(value, pattern) && is_total(value);
+            total_before(body, scope).map(|before| total && before)
+        }),
+    }
+}
+
+/// Whether it's a number if evaluating it doesn't fail
+fn is_number(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::Number(_)
+            | Expr::BinaryOperation(
+                _,
+                BinaryOpKind::Addition
+                    | BinaryOpKind::Subtraction
+                    | BinaryOpKind::Multiplication
+                    | BinaryOpKind::Division
+                    | BinaryOpKind::Remainder,
+                _
+            )
+            | Expr::Builtin {
+                builtin: Builtin::Length,
+                ..
+            }
+    )
+}
+
+/// Whether it refers only to the bindings it introduces itself
+fn is_closed(expr: &Expr, bound: &mut Vec<Scope>) -> bool {
+    match expr {
+        Expr::Number(_) | Expr::Bool(_) | Expr::Str(_) | Expr::Char(_) => true,
+        Expr::Referal { scope } => bound.contains(scope),
+        Expr::LambdaFunction { arg, body, .. } => {
+            bound.push(arg.scope);
+            let closed = is_closed(body, bound);
+            bound.pop();
+            closed
+        }
+        Expr::LetRec { bindings, body } => {
+            bound.extend(bindings.iter().map(|(binding, _)| binding.scope));
+            let closed =
+                bindings.iter().all(|(_, value)| is_closed(value, bound)) && is_closed(body, bound);
+            bound.truncate(bound.len() - bindings.len());
+            closed
+        }
+        Expr::BinaryOperation(lhs, _, rhs) => is_closed(lhs, bound) && is_closed(rhs, bound),
+        Expr::ProcCall { args, .. }
+        | Expr::Builtin { args, .. }
+        | Expr::Tuple(args)
+        | Expr::List(args) => args.iter().all(|arg| is_closed(arg, bound)),
+        Expr::Record(fields) => fields.iter().all(|(_, value)| is_closed(value, bound)),
+        Expr::Field { record, .. } => is_closed(record, bound),
+        Expr::If {
+            condition,
+            then,
+            otherwise,
+        } => is_closed(condition, bound) && is_closed(then, bound) && is_closed(otherwise, bound),
+        Expr::Destructure {
+            value,
+            pattern,
+            body,
+        } => {
+            let bindings = pattern.bindings();
+            bound.extend(bindings.iter().map(|binding| binding.scope));
+            let closed = is_closed(body, bound);
+            bound.truncate(bound.len() - bindings.len());
+            is_closed(value, bound) && closed
+        }
+    }
+}
+
+/// Whether the argument is known enough to specialize a proc on it
+fn is_known(expr: &Expr) -> bool {
+    match expr {
+        Expr::Number(_) | Expr::Bool(_) | Expr::Str(_) | Expr::Char(_) => true,
+        Expr::LambdaFunction { .. } => is_closed(expr, &mut vec![]),
+        _ => false,
+    }
+}
+
+/// Whether they're the same, up to the scopes of their bindings
+fn alpha_eq(a: &Expr, b: &Expr, bound: &mut Vec<(Scope, Scope)>) -> bool {
+    match (a, b) {
+        (Expr::Number(x), Expr::Number(y)) => x == y,
+        (Expr::Bool(x), Expr::Bool(y)) => x == y,
+        (Expr::Str(x), Expr::Str(y)) => x == y,
+        (Expr::Char(x), Expr::Char(y)) => x == y,
+        (Expr::Referal { scope: x }, Expr::Referal { scope: y }) => {
+            match bound.iter().rev().find(|(a, b)| a == x || b == y) {
+                Some(pair) => *pair == (*x, *y),
+                None => x == y,
+            }
+        }
+        (
+            Expr::LambdaFunction {
+                arg: x, body: a, ..
+            },
+            Expr::LambdaFunction {
+                arg: y, body: b, ..
+            },
+        ) => {
+            bound.push((x.scope, y.scope));
+            let eq = alpha_eq(a, b, bound);
+            bound.pop();
+            eq
+        }
+        (
+            Expr::LetRec {
+                bindings: x,
+                body: a,
+            },
+            Expr::LetRec {
+                bindings: y,
+                body: b,
+            },
+        ) if x.len() == y.len() => {
+            bound.extend(x.iter().zip(y).map(|((x, _), (y, _))| (x.scope, y.scope)));
+            let eq = x
+                .iter()
+                .zip(y)
+                .all(|((_, x), (_, y))| alpha_eq(x, y, bound))
+                && alpha_eq(a, b, bound);
+            bound.truncate(bound.len() - x.len());
+            eq
+        }
+        (Expr::BinaryOperation(a, x, b), Expr::BinaryOperation(c, y, d)) => {
+            x == y && alpha_eq(a, c, bound) && alpha_eq(b, d, bound)
+        }
+        (Expr::ProcCall { name: x, args: a }, Expr::ProcCall { name: y, args: b }) => {
+            x == y && a.len() == b.len() && a.iter().zip(b).all(|(a, b)| alpha_eq(a, b, bound))
+        }
+        (
+            Expr::Builtin {
+                builtin: x,
+                args: a,
+            },
+            Expr::Builtin {
+                builtin: y,
+                args: b,
+            },
+        ) => x == y && a.len() == b.len() && a.iter().zip(b).all(|(a, b)| alpha_eq(a, b, bound)),
+        (Expr::Tuple(a), Expr::Tuple(b)) | (Expr::List(a), Expr::List(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| alpha_eq(a, b, bound))
+        }
+        (Expr::Record(a), Expr::Record(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b)
+                    .all(|((x, a), (y, b))| x == y && alpha_eq(a, b, bound))
+        }
+        (
+            Expr::Field {
+                record: a,
+                field: x,
+                ..
+            },
+            Expr::Field {
+                record: b,
+                field: y,
+                ..
+            },
+        ) => x == y && alpha_eq(a, b, bound),
+        (
+            Expr::Destructure {
+                value: a,
+                pattern: x,
+                body: b,
+            },
+            Expr::Destructure {
+                value: c,
+                pattern: y,
+                body: d,
+            },
+        ) => {
+            let start = bound.len();
+            let eq = alpha_eq(a, c, bound) && pair_bindings(x, y, bound) && alpha_eq(b, d, bound);
+            bound.truncate(start);
+            eq
+        }
+        (
+            Expr::If {
+                condition: a,
+                then: b,
+                otherwise: c,
+            },
+            Expr::If {
+                condition: x,
+                then: y,
+                otherwise: z,
+            },
+        ) => alpha_eq(a, x, bound) && alpha_eq(b, y, bound) && alpha_eq(c, z, bound),
+        _ => false,
+    }
+}
+
+/// Whether they have the same shape, pairing up their bindings
+fn pair_bindings(x: &Pattern, y: &Pattern, bound: &mut Vec<(Scope, Scope)>) -> bool {
+    match (x, y) {
+        (Pattern::Binding(x), Pattern::Binding(y)) => {
+            bound.push((x.scope, y.scope));
+            true
+        }
+        (Pattern::Wildcard, Pattern::Wildcard) => true,
+        (Pattern::Number(x), Pattern::Number(y)) => x == y,
+        (Pattern::Bool(x), Pattern::Bool(y)) => x == y,
+        (Pattern::Tuple(x), Pattern::Tuple(y)) => {
+            x.len() == y.len() && x.iter().zip(y).all(|(x, y)| pair_bindings(x, y, bound))
+        }
+        _ => false,
+    }
+}
+
+fn size(expr: &Expr) -> usize {
+    1 + match expr {
+        Expr::Number(_) | Expr::Bool(_) | Expr::Str(_) | Expr::Char(_) | Expr::Referal { .. } => 0,
+        Expr::LambdaFunction { body, .. } => size(body),
+        Expr::LetRec { bindings, body } => {
+            bindings.iter().map(|(_, value)| size(value)).sum::<usize>() + size(body)
+        }
+        Expr::BinaryOperation(lhs, _, rhs) => size(lhs) + size(rhs),
+        Expr::ProcCall { args, .. }
+        | Expr::Builtin { args, .. }
+        | Expr::Tuple(args)
+        | Expr::List(args) => args.iter().map(|arg| size(arg)).sum(),
+        Expr::Record(fields) => fields.iter().map(|(_, value)| size(value)).sum(),
+        Expr::Field { record, .. } => size(record),
+        Expr::If {
+            condition,
+            then,
+            otherwise,
+        } => size(condition) + size(then) + size(otherwise),
+        Expr::Destructure { value, body, .. } => size(value) + size(body),
+    }
+}
+
+/// The procs it calls, once for every call site
+fn calls(expr: &Expr, found: &mut Vec<GlobalSymbol>) {
+    match expr {
+        Expr::Number(_) | Expr::Bool(_) | Expr::Str(_) | Expr::Char(_) | Expr::Referal { .. } => {}
+        Expr::LambdaFunction { body, .. } => calls(body, found),
+        Expr::LetRec { bindings, body } => {
+            bindings.iter().for_each(|(_, value)| calls(value, found));
+            calls(body, found);
+        }
+        Expr::BinaryOperation(lhs, _, rhs) => {
+            calls(lhs, found);
+            calls(rhs, found);
+        }
+        Expr::ProcCall { name, args } => {
+            found.push(*name);
+            args.iter().for_each(|arg| calls(arg, found));
+        }
+        Expr::Builtin { args, .. } | Expr::Tuple(args) | Expr::List(args) => {
+            args.iter().for_each(|arg| calls(arg, found))
+        }
+        Expr::Record(fields) => fields.iter().for_each(|(_, value)| calls(value, found)),
+        Expr::Field { record, .. } => calls(record, found),
+        Expr::Destructure { value, body, .. } => {
+            calls(value, found);
+            calls(body, found);
+        }
+        Expr::If {
+            condition,
+            then,
+            otherwise,
+        } => {
+            calls(condition, found);
+            calls(then, found);
+            calls(otherwise, found);
+        }
+    }
+}
+
+fn fold<'a>(x: i32, kind: BinaryOpKind, y: i32) -> Option<Expr<'a>> {
+    match kind {
+        // numbers wrap around on overflow, like at runtime
+        BinaryOpKind::Addition => Some(Expr::Number(x.wrapping_add(y))),
+        BinaryOpKind::Subtraction => Some(Expr::Number(x.wrapping_sub(y))),
+        BinaryOpKind::Multiplication => Some(Expr::Number(x.wrapping_mul(y))),
+        // dividing by zero is left to fail at runtime
+        BinaryOpKind::Division if y != 0 => Some(Expr::Number(x.wrapping_div(y))),
+        BinaryOpKind::Remainder if y != 0 => Some(Expr::Number(x.wrapping_rem(y))),
+        BinaryOpKind::Division | BinaryOpKind::Remainder => None,
+        BinaryOpKind::Call => None,
+        _ => kind.compare(x, y).map(Expr::Bool),
+    }
+}
+
+impl<'a> Optimizer<'a> {
+    fn alloc(&self, expr: Expr<'a>) -> &'a Expr<'a> {
+        self.arenas.level1.alloc(expr)
+    }
+
+    fn top(&self, name: GlobalSymbol) -> &'a Top<'a> {
+        self.tops
+            .iter()
+            .find(|top| matches!(top, Top::Procedure { name: n, .. } if *n == name))
+            .unwrap()
+    }
+
+    fn call_graph(&self) -> HashMap<GlobalSymbol, Vec<GlobalSymbol>> {
+        self.tops
+            .iter()
+            .map(|top| match top {
+                Top::Procedure { name, body, .. } => {
+                    let mut found = vec![];
+                    calls(body, &mut found);
+                    (*name, found)
+                }
+            })
+            .collect()
+    }
+
+    fn analyze(&mut self) {
+        let graph = self.call_graph();
+        let reaches_itself = |start: GlobalSymbol| {
+            let mut seen = HashSet::new();
+            let mut todo = graph[&start].clone();
+            while let Some(name) = todo.pop() {
+                if name == start {
+                    return true;
+                }
+                if seen.insert(name) {
+                    todo.extend(&graph[&name]);
+                }
+            }
+            false
+        };
+        self.procs = self
+            .tops
+            .iter()
+            .map(|top| match top {
+                Top::Procedure { name, body, .. } => {
+                    let info = ProcInfo {
+                        recursive: reaches_itself(*name),
+                        call_sites: graph.values().flatten().filter(|n| *n == name).count(),
+                        size: size(body),
+                    };
+                    (*name, info)
+                }
+            })
+            .collect();
+    }
+
+    /// Removes the procs main doesn't call anymore
+    fn remove_unreachable(&mut self) {
+        let graph = self.call_graph();
+        let main = GlobalSymbol(Ident::new("main"));
+        let mut reachable = HashSet::from([main]);
+        let mut todo = vec![main];
+        while let Some(name) = todo.pop() {
+            for callee in &graph[&name] {
+                if reachable.insert(*callee) {
+                    todo.push(*callee);
+                }
+            }
+        }
+        self.tops
+            .retain(|top| matches!(top, Top::Procedure { name, .. } if reachable.contains(name)));
+    }
+
+    fn fresh_binding(&mut self, binding: Binding, renames: &mut HashMap<Scope, Scope>) -> Binding {
+        let scope = Scope::new();
+        if let Some(&name) = self.names.get(&binding.scope) {
+            self.names.insert(scope, name);
+        }
+        renames.insert(binding.scope, scope);
+        Binding { scope }
+    }
+
+    fn fresh_pattern(&mut self, pattern: &Pattern, renames: &mut HashMap<Scope, Scope>) -> Pattern {
+        match pattern {
+            Pattern::Binding(binding) => Pattern::Binding(self.fresh_binding(*binding, renames)),
+            Pattern::Tuple(patterns) => Pattern::Tuple(
+                patterns
+                    .iter()
+                    .map(|pattern| self.fresh_pattern(pattern, renames))
+                    .collect(),
+            ),
+            Pattern::Wildcard | Pattern::Number(_) | Pattern::Bool(_) => pattern.clone(),
+        }
+    }
+
+    /// A copy of the expression, with new scopes for the bindings it introduces
+    fn freshen(&mut self, expr: &'a Expr<'a>, renames: &mut HashMap<Scope, Scope>) -> &'a Expr<'a> {
+        match expr {
+            Expr::Number(_) | Expr::Bool(_) | Expr::Str(_) | Expr::Char(_) => expr,
+            Expr::Referal { scope } => match renames.get(scope) {
+                Some(&scope) => self.alloc(Expr::Referal { scope }),
+                None => expr,
+            },
+            Expr::LambdaFunction {
+                arg,
+                body,
+                captured,
+            } => {
+                let arg = self.fresh_binding(*arg, renames);
+                let body = self.freshen(body, renames);
+                let captured = captured
+                    .iter()
+                    .map(|b| Binding {
+                        scope: renames.get(&b.scope).copied().unwrap_or(b.scope),
+                    })
+                    .collect();
+                self.alloc(Expr::LambdaFunction {
+                    arg,
+                    body,
+                    captured,
+                })
+            }
+            Expr::LetRec { bindings, body } => {
+                let fresh = bindings
+                    .iter()
+                    .map(|(binding, _)| self.fresh_binding(*binding, renames))
+                    .collect::<Vec<_>>();
+                let bindings = fresh
+                    .into_iter()
+                    .zip(bindings)
+                    .map(|(binding, (_, value))| (binding, self.freshen(value, renames)))
+                    .collect();
+                let body = self.freshen(body, renames);
+                self.alloc(Expr::LetRec { bindings, body })
+            }
+            Expr::BinaryOperation(lhs, kind, rhs) => {
+                let lhs = self.freshen(lhs, renames);
+                let rhs = self.freshen(rhs, renames);
+                self.alloc(Expr::BinaryOperation(lhs, *kind, rhs))
+            }
+            Expr::ProcCall { name, args } => {
+                let args = args.iter().map(|arg| self.freshen(arg, renames)).collect();
+                self.alloc(Expr::ProcCall { name: *name, args })
+            }
+            Expr::Builtin { builtin, args } => {
+                let args = args.iter().map(|arg| self.freshen(arg, renames)).collect();
+                self.alloc(Expr::Builtin {
+                    builtin: *builtin,
+                    args,
+                })
+            }
+            Expr::Tuple(elements) => {
+                let elements = elements.iter().map(|e| self.freshen(e, renames)).collect();
+                self.alloc(Expr::Tuple(elements))
+            }
+            Expr::List(elements) => {
+                let elements = elements.iter().map(|e| self.freshen(e, renames)).collect();
+                self.alloc(Expr::List(elements))
+            }
+            Expr::Record(fields) => {
+                let fields = fields
+                    .iter()
+                    .map(|(field, value)| (*field, self.freshen(value, renames)))
+                    .collect();
+                self.alloc(Expr::Record(fields))
+            }
+            Expr::Field { record, field, idx } => {
+                let record = self.freshen(record, renames);
+                self.alloc(Expr::Field {
+                    record,
+                    field: *field,
+                    idx: *idx,
+                })
+            }
+            Expr::Destructure {
+                value,
+                pattern,
+                body,
+            } => {
+                let value = self.freshen(value, renames);
+                let pattern = self.fresh_pattern(pattern, renames);
+                let body = self.freshen(body, renames);
+                self.alloc(Expr::Destructure {
+                    value,
+                    pattern,
+                    body,
+                })
+            }
+            Expr::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let condition = self.freshen(condition, renames);
+                let then = self.freshen(then, renames);
+                let otherwise = self.freshen(otherwise, renames);
+                self.alloc(Expr::If {
+                    condition,
+                    then,
+                    otherwise,
+                })
+            }
+        }
+    }
+
+    /// A copy of the proc with fresh bindings, as its parameters and body
+    fn freshen_proc(&mut self, name: GlobalSymbol) -> (Vec<(Binding, Type)>, &'a Expr<'a>) {
+        let Top::Procedure { args, body, .. } = self.top(name);
+        let mut renames = HashMap::new();
+        let args = args
+            .iter()
+            .map(|(binding, typ)| (self.fresh_binding(*binding, &mut renames), typ.clone()))
+            .collect();
+        (args, self.freshen(body, &mut renames))
+    }
+
+    fn simplify(&mut self, expr: &'a Expr<'a>) -> &'a Expr<'a> {
+        match expr {
+            Expr::Number(_)
+            | Expr::Bool(_)
+            | Expr::Str(_)
+            | Expr::Char(_)
+            | Expr::Referal { .. } => expr,
+            Expr::LambdaFunction {
+                arg,
+                body,
+                captured,
+            } => {
+                let body = self.simplify(body);
+                self.alloc(Expr::LambdaFunction {
+                    arg: *arg,
+                    body,
+                    captured: captured.clone(),
+                })
+            }
+            Expr::LetRec { bindings, body } => {
+                let bindings = bindings
+                    .iter()
+                    .map(|(binding, value)| (*binding, self.simplify(value)))
+                    .collect();
+                let body = self.simplify(body);
+                self.alloc(Expr::LetRec { bindings, body })
+            }
+            Expr::BinaryOperation(lhs, kind, rhs) => {
+                let lhs = self.simplify(lhs);
+                let rhs = self.simplify(rhs);
+                self.rewrite(lhs, *kind, rhs)
+            }
+            Expr::ProcCall { name, args } => {
+                let args = args.iter().map(|arg| self.simplify(arg)).collect();
+                self.call(*name, args)
+            }
+            Expr::Builtin { builtin, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.simplify(arg))
+                    .collect::<Vec<_>>();
+                match (builtin, args.as_slice()) {
+                    (Builtin::Length, [Expr::Str(s)]) => {
+                        self.changed = true;
+                        self.alloc(Expr::Number(s.chars().count() as i32))
+                    }
+                    (Builtin::Length, [Expr::List(elements)])
+                        if elements.iter().all(|element| is_total(element)) =>
+                    {
+                        self.changed = true;
+                        self.alloc(Expr::Number(elements.len() as i32))
+                    }
+                    _ => self.alloc(Expr::Builtin {
+                        builtin: *builtin,
+                        args,
+                    }),
+                }
+            }
+            Expr::Tuple(elements) => {
+                let elements = elements.iter().map(|e| self.simplify(e)).collect();
+                self.alloc(Expr::Tuple(elements))
+            }
+            Expr::List(elements) => {
+                let elements = elements.iter().map(|e| self.simplify(e)).collect();
+                self.alloc(Expr::List(elements))
+            }
+            Expr::Record(fields) => {
+                let fields = fields
+                    .iter()
+                    .map(|(field, value)| (*field, self.simplify(value)))
+                    .collect();
+                self.alloc(Expr::Record(fields))
+            }
+            Expr::Field { record, field, idx } => match self.simplify(record) {
+                // {x = a, y = b}.y -> b, if a can be dropped
+                Expr::Record(fields)
+                    if fields.get(*idx).is_some_and(|(name, _)| name == field)
+                        && fields
+                            .iter()
+                            .enumerate()
+                            .all(|(i, (_, value))| i == *idx || is_total(value)) =>
+                {
+                    self.changed = true;
+                    fields[*idx].1
+                }
+                record => self.alloc(Expr::Field {
+                    record,
+                    field: *field,
+                    idx: *idx,
+                }),
+            },
+            Expr::Destructure {
+                value,
+                pattern,
+                body,
+            } => {
+                let value = self.simplify(value);
+                let body = self.simplify(body);
+                self.destructure(value, pattern.clone(), body)
+            }
+            Expr::If {
+                condition,
+                then,
+                otherwise,
+            } => match self.simplify(condition) {
+                Expr::Bool(true) => {
+                    self.changed = true;
+                    self.simplify(then)
+                }
+                Expr::Bool(false) => {
+                    self.changed = true;
+                    self.simplify(otherwise)
+                }
+                condition => {
+                    let then = self.simplify(then);
+                    let otherwise = self.simplify(otherwise);
+                    self.alloc(Expr::If {
+                        condition,
+                        then,
+                        otherwise,
+                    })
+                }
+            },
+        }
+    }
+
+    /// Simplifies a binary operation, whose operands are already simplified
+    fn rewrite(
+        &mut self,
+        lhs: &'a Expr<'a>,
+        kind: BinaryOpKind,
+        rhs: &'a Expr<'a>,
+    ) -> &'a Expr<'a> {
+        use BinaryOpKind as Op;
+        let rewritten = match (lhs, kind, rhs) {
+            (Expr::Number(x), _, Expr::Number(y)) => {
+                fold(*x, kind, *y).map(|folded| self.alloc(folded))
+            }
+            (Expr::Bool(x), Op::Equal, Expr::Bool(y)) => Some(self.alloc(Expr::Bool(x == y))),
+            (Expr::Bool(x), Op::NotEqual, Expr::Bool(y)) => Some(self.alloc(Expr::Bool(x != y))),
+            (Expr::Str(x), Op::Concatenation, Expr::Str(y)) => {
+                let s = self.arenas.strings.alloc(format!("{x}{y}"));
+                Some(self.alloc(Expr::Str(s)))
+            }
+            (Expr::Char(x), _, Expr::Char(y)) => kind
+                .compare(*x as i32, *y as i32)
+                .map(|result| self.alloc(Expr::Bool(result))),
+            // only when the other operand can't make it fail
+            (
+                Expr::Str(""),
+                Op::Concatenation,
+                other @ Expr::BinaryOperation(_, Op::Concatenation, _),
+            )
+            | (
+                other @ Expr::BinaryOperation(_, Op::Concatenation, _),
+                Op::Concatenation,
+                Expr::Str(""),
+            ) => Some(other),
+            (Expr::Number(0), Op::Addition, other)
+            | (other, Op::Addition | Op::Subtraction, Expr::Number(0))
+            | (Expr::Number(1), Op::Multiplication, other)
+            | (other, Op::Multiplication | Op::Division, Expr::Number(1))
+                if is_number(other) =>
+            {
+                Some(other)
+            }
+            (Expr::LambdaFunction { arg, body, .. }, Op::Call, value) => {
+                let mut uses = Uses::default();
+                count_uses(body, arg.scope, false, &mut uses);
+                let reducible = match value {
+                    Expr::Number(_) | Expr::Char(_) | Expr::Referal { .. } => true,
+                    Expr::LambdaFunction { .. } => uses.count <= 1,
+                    // moved to where it's used, over work that surely stops
+                    _ => {
+                        (uses.count == 1
+                            && !uses.under_lambda
+                            && (is_total(value) || total_before(body, arg.scope) == Some(true)))
+                            || (uses.count == 0 && is_total(value))
+                    }
+                };
+                reducible.then(|| self.substitute(body, arg.scope, value))
+            }
+            _ => None,
+        };
+        match rewritten {
+            Some(expr) => {
+                self.changed = true;
+                expr
+            }
+            None => self.alloc(Expr::BinaryOperation(lhs, kind, rhs)),
+        }
+    }
+
+    /// Simplifies a destructuring, whose value and body are already simplified
+    fn destructure(
+        &mut self,
+        value: &'a Expr<'a>,
+        pattern: Pattern,
+        body: &'a Expr<'a>,
+    ) -> &'a Expr<'a> {
+        match (value, pattern) {
+            // let x = value in body -> (x -> body)(value)
+            (_, Pattern::Binding(arg)) => {
+                self.changed = true;
+                let fun = self.alloc(Expr::LambdaFunction {
+                    arg,
+                    body,
+                    captured: HashSet::new(),
+                });
+                self.rewrite(fun, BinaryOpKind::Call, value)
+            }
+            (_, Pattern::Wildcard) if is_total(value) => {
+                self.changed = true;
+                body
+            }
+            // let (a, b) = (x, y) in body -> let a = x in let b = y in body
+            (Expr::Tuple(elements), Pattern::Tuple(patterns))
+                if elements.len() == patterns.len() =>
+            {
+                self.changed = true;
+                elements
+                    .iter()
+                    .zip(patterns)
+                    .rev()
+                    .fold(body, |body, (value, pattern)| {
+                        self.alloc(Expr::Destructure {
+                            value,
+                            pattern,
+                            body,
+                        })
+                    })
+            }
+            (Expr::Number(x), Pattern::Number(y)) if *x == y => {
+                self.changed = true;
+                body
+            }
+            (Expr::Bool(x), Pattern::Bool(y)) if *x == y => {
+                self.changed = true;
+                body
+            }
+            (_, pattern) => self.alloc(Expr::Destructure {
+                value,
+                pattern,
+                body,
+            }),
+        }
+    }
+
+    /// Inlines or specializes the call, whose arguments are already simplified
+    fn call(&mut self, name: GlobalSymbol, args: Vec<&'a Expr<'a>>) -> &'a Expr<'a> {
+        let inlined = self.procs.get(&name).is_some_and(|info| {
+            !info.recursive && (info.size <= INLINE_SIZE || info.call_sites == 1)
+        });
+        if inlined {
+            // proc!(a, b) -> let x = a in let y = b in body
+            self.changed = true;
+            let (params, body) = self.freshen_proc(name);
+            return params
+                .into_iter()
+                .zip(args)
+                .rev()
+                .fold(body, |body, ((arg, _), value)| {
+                    let fun = self.alloc(Expr::LambdaFunction {
+                        arg,
+                        body,
+                        captured: HashSet::new(),
+                    });
+                    self.alloc(Expr::BinaryOperation(fun, BinaryOpKind::Call, value))
+                });
+        }
+        let known = args
+            .iter()
+            .map(|arg| is_known(arg).then_some(*arg))
+            .collect::<Vec<_>>();
+        if known.iter().all(Option::is_none) {
+            return self.alloc(Expr::ProcCall { name, args });
+        }
+        let existing = self.specializations.iter().find(|s| {
+            s.of == name
+                && s.known.iter().zip(&known).all(|(a, b)| match (a, b) {
+                    (Some(a), Some(b)) => alpha_eq(a, b, &mut vec![]),
+                    (None, None) => true,
+                    _ => false,
+                })
+        });
+        let specialized = match existing {
+            Some(specialization) => specialization.name,
+            None if self.specializations.len() < MAX_SPECIALIZATIONS => {
+                self.specialize(name, &known)
+            }
+            None => return self.alloc(Expr::ProcCall { name, args }),
+        };
+        self.changed = true;
+        let args = args
+            .into_iter()
+            .zip(&known)
+            .filter(|(_, known)| known.is_none())
+            .map(|(arg, _)| arg)
+            .collect();
+        self.alloc(Expr::ProcCall {
+            name: specialized,
+            args,
+        })
+    }
+
+    /// Makes a copy of the proc without the known parameters
+    fn specialize(&mut self, name: GlobalSymbol, known: &[Option<&'a Expr<'a>>]) -> GlobalSymbol {
+        let Top::Procedure { return_type, .. } = self.top(name);
+        let (params, mut body) = self.freshen_proc(name);
+        let mut args = vec![];
+        for ((param, typ), known) in params.into_iter().zip(known) {
+            match known {
+                Some(value) => body = self.substitute(body, param.scope, value),
+                None => args.push((param, typ)),
+            }
+        }
+        let specialized = GlobalSymbol(Ident::new(&format!(
+            "{name}.{}",
+            self.specializations.len() + 1
+        )));
+        self.specializations.push(Specialization {
+            of: name,
+            known: known.to_vec(),
+            name: specialized,
+        });
+        self.tops.push(self.arenas.tops.alloc(Top::Procedure {
+            name: specialized,
+            args,
+            return_type: return_type.clone(),
+            body,
+        }));
+        specialized
+    }
+
+    /// Replaces the binding with the value, copying functions with fresh bindings
+    fn substitute(
+        &mut self,
+        expr: &'a Expr<'a>,
+        scope: Scope,
+        value: &'a Expr<'a>,
+    ) -> &'a Expr<'a> {
+        match expr {
+            Expr::Referal { scope: s } if *s == scope => match value {
+                Expr::LambdaFunction { .. } => self.freshen(value, &mut HashMap::new()),
+                _ => value,
+            },
+            Expr::Number(_)
+            | Expr::Bool(_)
+            | Expr::Str(_)
+            | Expr::Char(_)
+            | Expr::Referal { .. } => expr,
+            Expr::LambdaFunction {
+                arg,
+                body,
+                captured,
+            } => {
+                let body = self.substitute(body, scope, value);
+                self.alloc(Expr::LambdaFunction {
+                    arg: *arg,
+                    body,
+                    captured: captured.clone(),
+                })
+            }
+            Expr::LetRec { bindings, body } => {
+                let bindings = bindings
+                    .iter()
+                    .map(|(binding, v)| (*binding, self.substitute(v, scope, value)))
+                    .collect();
+                let body = self.substitute(body, scope, value);
+                self.alloc(Expr::LetRec { bindings, body })
+            }
+            Expr::BinaryOperation(lhs, kind, rhs) => {
+                let lhs = self.substitute(lhs, scope, value);
+                let rhs = self.substitute(rhs, scope, value);
+                self.alloc(Expr::BinaryOperation(lhs, *kind, rhs))
+            }
+            Expr::ProcCall { name, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.substitute(arg, scope, value))
+                    .collect();
+                self.alloc(Expr::ProcCall { name: *name, args })
+            }
+            Expr::Builtin { builtin, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.substitute(arg, scope, value))
+                    .collect();
+                self.alloc(Expr::Builtin {
+                    builtin: *builtin,
+                    args,
+                })
+            }
+            Expr::Tuple(elements) => {
+                let elements = elements
+                    .iter()
+                    .map(|e| self.substitute(e, scope, value))
+                    .collect();
+                self.alloc(Expr::Tuple(elements))
+            }
+            Expr::List(elements) => {
+                let elements = elements
+                    .iter()
+                    .map(|e| self.substitute(e, scope, value))
+                    .collect();
+                self.alloc(Expr::List(elements))
+            }
+            Expr::Record(fields) => {
+                let fields = fields
+                    .iter()
+                    .map(|(field, v)| (*field, self.substitute(v, scope, value)))
+                    .collect();
+                self.alloc(Expr::Record(fields))
+            }
+            Expr::Field { record, field, idx } => {
+                let record = self.substitute(record, scope, value);
+                self.alloc(Expr::Field {
+                    record,
+                    field: *field,
+                    idx: *idx,
+                })
+            }
+            Expr::Destructure {
+                value: v,
+                pattern,
+                body,
+            } => {
+                let v = self.substitute(v, scope, value);
+                let body = self.substitute(body, scope, value);
+                self.alloc(Expr::Destructure {
+                    value: v,
+                    pattern: pattern.clone(),
+                    body,
+                })
+            }
+            Expr::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let condition = self.substitute(condition, scope, value);
+                let then = self.substitute(then, scope, value);
+                let otherwise = self.substitute(otherwise, scope, value);
+                self.alloc(Expr::If {
+                    condition,
+                    then,
+                    otherwise,
+                })
+            }
+        }
+    }
+
+    /// Gives every lambda the bindings its body refers to now, returning the free ones
+    fn recapture(&self, expr: &'a Expr<'a>) -> (&'a Expr<'a>, HashSet<Binding>) {
+        match expr {
+            Expr::Number(_) | Expr::Bool(_) | Expr::Str(_) | Expr::Char(_) => {
+                (expr, HashSet::new())
+            }
+            Expr::Referal { scope } => (expr, HashSet::from([Binding { scope: *scope }])),
+            Expr::LambdaFunction { arg, body, .. } => {
+                let (body, mut captured) = self.recapture(body);
+                captured.remove(arg);
+                let free = captured.clone();
+                let expr = self.alloc(Expr::LambdaFunction {
+                    arg: *arg,
+                    body,
+                    captured,
+                });
+                (expr, free)
+            }
+            Expr::LetRec { bindings, body } => {
+                let (body, mut free) = self.recapture(body);
+                let bindings = bindings
+                    .iter()
+                    .map(|(binding, value)| {
+                        let (value, value_free) = self.recapture(value);
+                        free.extend(value_free);
+                        (*binding, value)
+                    })
+                    .collect::<Vec<_>>();
+                for (binding, _) in &bindings {
+                    free.remove(binding);
+                }
+                (self.alloc(Expr::LetRec { bindings, body }), free)
+            }
+            Expr::BinaryOperation(lhs, kind, rhs) => {
+                let (lhs, mut free) = self.recapture(lhs);
+                let (rhs, rhs_free) = self.recapture(rhs);
+                free.extend(rhs_free);
+                (self.alloc(Expr::BinaryOperation(lhs, *kind, rhs)), free)
+            }
+            Expr::ProcCall { name, args } => {
+                let mut free = HashSet::new();
+                let args = args
+                    .iter()
+                    .map(|arg| {
+                        let (arg, arg_free) = self.recapture(arg);
+                        free.extend(arg_free);
+                        arg
+                    })
+                    .collect();
+                (self.alloc(Expr::ProcCall { name: *name, args }), free)
+            }
+            Expr::Builtin { builtin, args } => {
+                let mut free = HashSet::new();
+                let args = args
+                    .iter()
+                    .map(|arg| {
+                        let (arg, arg_free) = self.recapture(arg);
+                        free.extend(arg_free);
+                        arg
+                    })
+                    .collect();
+                let expr = self.alloc(Expr::Builtin {
+                    builtin: *builtin,
+                    args,
+                });
+                (expr, free)
+            }
+            Expr::Tuple(elements) => {
+                let mut free = HashSet::new();
+                let elements = elements
+                    .iter()
+                    .map(|element| {
+                        let (element, element_free) = self.recapture(element);
+                        free.extend(element_free);
+                        element
+                    })
+                    .collect();
+                (self.alloc(Expr::Tuple(elements)), free)
+            }
+            Expr::List(elements) => {
+                let mut free = HashSet::new();
+                let elements = elements
+                    .iter()
+                    .map(|element| {
+                        let (element, element_free) = self.recapture(element);
+                        free.extend(element_free);
+                        element
+                    })
+                    .collect();
+                (self.alloc(Expr::List(elements)), free)
+            }
+            Expr::Record(fields) => {
+                let mut free = HashSet::new();
+                let fields = fields
+                    .iter()
+                    .map(|(field, value)| {
+                        let (value, value_free) = self.recapture(value);
+                        free.extend(value_free);
+                        (*field, value)
+                    })
+                    .collect();
+                (self.alloc(Expr::Record(fields)), free)
+            }
+            Expr::Field { record, field, idx } => {
+                let (record, free) = self.recapture(record);
+                let expr = self.alloc(Expr::Field {
+                    record,
+                    field: *field,
+                    idx: *idx,
+                });
+                (expr, free)
+            }
+            Expr::Destructure {
+                value,
+                pattern,
+                body,
+            } => {
+                let (body, mut free) = self.recapture(body);
+                for binding in pattern.bindings() {
+                    free.remove(&binding);
+                }
+                let (value, value_free) = self.recapture(value);
+                free.extend(value_free);
+                let expr = self.alloc(Expr::Destructure {
+                    value,
+                    pattern: pattern.clone(),
+                    body,
+                });
+                (expr, free)
+            }
+            Expr::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let (condition, mut free) = self.recapture(condition);
+                let (then, then_free) = self.recapture(then);
+                let (otherwise, otherwise_free) = self.recapture(otherwise);
+                free.extend(then_free);
+                free.extend(otherwise_free);
+                let expr = self.alloc(Expr::If {
+                    condition,
+                    then,
+                    otherwise,
+                });
+                (expr, free)
+            }
+        }
+    }
+
+    /// Simplifies a proc until nothing changes, telling whether anything did
+    fn simplify_top(&mut self, idx: usize) -> bool {
+        let Top::Procedure {
+            name,
+            args,
+            return_type,
+            body,
+        } = self.tops[idx];
+        let mut body = *body;
+        let mut changed = false;
+        // rewrites make the program smaller and inlining and specializing is limited,
+        // so this stops
+        loop {
+            self.changed = false;
+            body = self.simplify(body);
+            if !self.changed {
+                break;
+            }
+            changed = true;
+        }
+        let (body, _) = self.recapture(body);
+        self.tops[idx] = self.arenas.tops.alloc(Top::Procedure {
+            name: *name,
+            args: args.clone(),
+            return_type: return_type.clone(),
+            body,
+        });
+        changed
+    }
+}
+
+impl<'a> FinalLevel<'a> {
+    pub fn optimize(self) -> Self {
+        let mut optimizer = Optimizer {
+            arenas: self.arenas,
+            names: self.names,
+            tops: self.tops,
+            procs: HashMap::new(),
+            specializations: vec![],
+            changed: false,
+        };
+        for _ in 0..ROUNDS {
+            optimizer.analyze();
+            let mut changed = false;
+            // specializing adds procs while iterating
+            let mut idx = 0;
+            while idx < optimizer.tops.len() {
+                changed |= optimizer.simplify_top(idx);
+                idx += 1;
+            }
+            if !changed {
+                break;
+            }
+        }
+        optimizer.remove_unreachable();
+        Self {
+            tops: optimizer.tops,
+            names: optimizer.names,
+            arenas: self.arenas,
+        }
+    }
+}
@@ -1,5 +1,7 @@
-use super::InitialLevel;
-use super::level0::{BinaryOpKind, Binding, Expr, GlobalSymbol, Top, Type};
+use super::level0::{
+    Associativity, Binding, Expr, Fixity, GlobalSymbol, Notation, Number, Operator, Top, Type,
+};
+use super::{Arenas, InitialLevel, Line};
 use crate::common::Ident;
 use chumsky::prelude::*;
 
@@ -9,6 +11,66 @@ macro_rules! parser {
     }
 }
 
+/// Whitespace and comments, `//` to the end of the line or nestable `/* */`.
+/// Doc comments are too, unless they belong to the proc after them
+fn whitespace<'src>() -> parser!('src: ()) {
+    // checked before a proc, without whitespace() which would recurse
+    let documents_proc = doc_line()
+        .padded_by(plain_whitespace())
+        .repeated()
+        .then(just("proc"))
+        .rewind();
+    let stray_doc = documents_proc.not().ignore_then(doc_line()).ignored();
+    choice((plain_comment(), stray_doc))
+        .repeated()
+        .labelled("whitespace")
+}
+
+/// Whitespace and comments except doc comments
+fn plain_whitespace<'src>() -> parser!('src: ()) {
+    plain_comment().repeated()
+}
+
+/// A whitespace char or a comment that isn't a doc comment
+fn plain_comment<'src>() -> parser!('src: ()) {
+    let line = doc_comment_start()
+        .not()
+        .ignore_then(just("//"))
+        .then(any().and_is(text::newline().not()).repeated())
+        .ignored();
+    let block = recursive(|block| {
+        just("/*")
+            .then(choice((block, any().and_is(just("*/").not()).ignored())).repeated())
+            .then(just("*/"))
+            .ignored()
+    });
+    choice((
+        any().filter(|c: &char| c.is_whitespace()).ignored(),
+        line,
+        block,
+    ))
+}
+
+/// `///`, but not `////`, which is a normal comment
+fn doc_comment_start<'src>() -> parser!('src: ()) {
+    just("///").then(just("/").not()).ignored()
+}
+
+/// A `///` comment, without the slashes
+fn doc_line<'src>() -> parser!('src: &'src str) {
+    doc_comment_start().ignore_then(any().and_is(text::newline().not()).repeated().to_slice())
+}
+
+/// The lines of the `///` comments before a proc, without the space after the slashes
+fn doc_comment<'src>() -> parser!('src: Vec<String>) {
+    doc_line()
+        .map(|line: &str| line.strip_prefix(' ').unwrap_or(line).trim_end().to_owned())
+        .padded_by(plain_whitespace())
+        .repeated()
+        .collect()
+        .labelled("doc comment")
+}
+
 macro_rules! keywords {
     ($($kw:literal <= $kw_parser:ident)*) => {
         fn is_kw(s: &str) -> bool {
@@ -16,14 +78,14 @@ macro_rules! keywords {
             false
         }
         $(
-            fn $kw_parser<'a>() -> parser!('a: ()) {
-                just($kw).padded().ignored().labelled($kw)
+            fn $kw_parser<'src>() -> parser!('src: ()) {
+                just($kw).padded_by(whitespace()).ignored().labelled($kw)
             }
         )*
     };
 }
 
-fn ident<'a>() -> parser!('a: Ident<'a>) {
+fn ident<'src>() -> parser!('src: Ident) {
     any()
         .filter(|c| matches!(c, 'a'..='z' | 'A'..='Z' | '_'))
         .then(
@@ -33,8 +95,8 @@ fn ident<'a>() -> parser!('a: Ident<'a>) {
         )
         .to_slice()
         .filter(|&s| !is_kw(s))
-        .map(Ident)
-        .padded()
+        .map(Ident::new)
+        .padded_by(whitespace())
         .labelled("identifier")
 }
 
@@ -42,113 +104,539 @@ keywords! {
     "let" <= kw_let
     "proc" <= kw_proc
     "in" <= kw_in
+    "rec" <= kw_rec
+    "and" <= kw_and
+    "if" <= kw_if
+    "then" <= kw_then
+    "else" <= kw_else
+    "true" <= kw_true
+    "false" <= kw_false
+    "_" <= kw_wildcard
+    "where" <= kw_where
+    "infixl" <= kw_infixl
+    "infixr" <= kw_infixr
+    "infix" <= kw_infix
+    "record" <= kw_record
+    "with" <= kw_with
+}
+
+/// What follows the body of a postfix binding
+#[derive(Debug, Clone)]
+enum Postfix<'a> {
+    Lambda(Vec<Binding<'a>>),         // body <- x y
+    Where(Binding<'a>, &'a Expr<'a>), // body where x = value
+}
+
+/// What follows an operand after a dot
+#[derive(Debug, Clone)]
+enum Suffix<'a> {
+    Method(GlobalSymbol, Vec<&'a Expr<'a>>), // a.name!(b)
+    Index(&'a Expr<'a>),                     // list.[index]
+    Field(Ident),                            // record.field
+}
+
+/// The value of a number literal, like `-0x_ff_i32`, that starts with a digit after the sign,
+/// and the type of its suffix
+fn number_value<'src>(
+    literal: &str,
+    span: SimpleSpan,
+) -> Result<(i32, Option<Type>), Rich<'src, char>> {
+    let error = |range: std::ops::Range<usize>, message: String| {
+        Rich::custom(
+            SimpleSpan::from(span.start + range.start..span.start + range.end),
+            message,
+        )
+    };
+    let negative = literal.starts_with('-');
+    let start = usize::from(negative);
+    let (radix, name, start) = match literal.get(start..start + 2) {
+        Some("0x") => (16, "hexadecimal", start + 2),
+        Some("0o") => (8, "octal", start + 2),
+        Some("0b") => (2, "binary", start + 2),
+        _ => (10, "decimal", start),
+    };
+    // the suffix starts at the first letter that can't be a digit
+    let end = literal[start..]
+        .find(|c: char| c.is_ascii_alphabetic() && !c.is_digit(radix.max(10)))
+        .map_or(literal.len(), |idx| start + idx);
+    let suffix = match &literal[end..] {
+        "" => None,
+        suffix => r#type()
+            .parse(suffix)
+            .into_output()
+            .filter(|typ| typ.range().is_some())
+            .ok_or_else(|| {
+                error(
+                    end..literal.len(),
+                    format!("`{suffix}` isn't the type of a number"),
+                )
+            })
+            .map(Some)?,
+    };
+    let typ = suffix.clone().unwrap_or(Type::I32);
+    let mut magnitude = Some(0i64);
+    for (idx, c) in literal[start..end].char_indices() {
+        let idx = start + idx;
+        if c == '_' {
+            continue;
+        }
+        let Some(digit) = c.to_digit(radix) else {
+            return Err(error(
+                idx..idx + 1,
+                format!("`{c}` isn't a digit in {name}"),
+            ));
+        };
+        magnitude = magnitude
+            .and_then(|n| n.checked_mul(radix.into()))
+            .and_then(|n| n.checked_add(digit.into()));
+    }
+    if !literal[start..end].contains(|c| c != '_') {
+        return Err(error(start..end, format!("expected {name} digits")));
+    }
+    let range = typ.range().unwrap();
+    magnitude
+        .map(|n| if negative { -n } else { n })
+        .filter(|n| range.contains(n))
+        .and_then(|n| i32::try_from(n).ok())
+        .ok_or_else(|| {
+            error(
+                0..literal.len(),
+                format!(
+                    "`{literal}` doesn't fit in {typ}, whose numbers are {}..={}",
+                    range.start(),
+                    range.end()
+                ),
+            )
+        })
+        .map(|value| (value, suffix))
 }
 
-fn number<'a>() -> parser!('a: i32) {
-    text::int(10)
-        .try_map(|s: &str, span| s.parse().map_err(|e| Rich::custom(span, e)))
-        .padded()
+fn number<'src, 'a: 'src>(arenas: &'a Arenas<'a>) -> parser!('src: Number<'a>) {
+    just('-')
+        .or_not()
+        .then(any().filter(char::is_ascii_digit))
+        .then(
+            any()
+                .filter(|c: &char| c.is_ascii_alphanumeric() || *c == '_')
+                .repeated(),
+        )
+        .to_slice()
+        .validate(
+            move |literal: &str, e, emitter| match number_value(literal, e.span()) {
+                Ok((value, suffix)) => Number {
+                    value,
+                    suffix: suffix.map(|suffix| arenas.types.alloc(suffix)),
+                },
+                Err(err) => {
+                    emitter.emit(err);
+                    Number::from(0)
+                }
+            },
+        )
+        .padded_by(whitespace())
         .labelled("number")
 }
 
-fn op<'a>(x: &'static str) -> parser!('a: ()) {
-    just(x).padded().ignored()
+/// A char of a string or char literal, which can be `\n`, `\t`, `\r`, `\0`, `\\`,
+/// `\"`, `\'` or `\u{...}` escapes, as printed by `Quoted`
+fn literal_char<'src>(quote: char) -> parser!('src: char) {
+    let unicode = text::digits(16)
+        .to_slice()
+        .delimited_by(just("u{"), just('}'))
+        .validate(|digits: &str, e, emitter| {
+            u32::from_str_radix(digits, 16)
+                .ok()
+                .and_then(char::from_u32)
+                .unwrap_or_else(|| {
+                    emitter.emit(Rich::custom(
+                        e.span(),
+                        format!("`{digits}` isn't the code of a char"),
+                    ));
+                    char::REPLACEMENT_CHARACTER
+                })
+        });
+    let escape = just('\\').ignore_then(choice((
+        just('n').to('\n'),
+        just('t').to('\t'),
+        just('r').to('\r'),
+        just('0').to('\0'),
+        just('\\'),
+        just('"'),
+        just('\''),
+        unicode,
+    )));
+    choice((escape, any().filter(move |&c| c != quote && c != '\\')))
+}
+
+fn string<'src>() -> parser!('src: String) {
+    literal_char('"')
+        .repeated()
+        .collect()
+        .delimited_by(just('"'), just('"'))
+        .padded_by(whitespace())
+        .labelled("string")
+}
+
+fn character<'src>() -> parser!('src: char) {
+    literal_char('\'')
+        .delimited_by(just('\''), just('\''))
+        .padded_by(whitespace())
+        .labelled("char")
+}
+
+fn op<'src>(x: &'static str) -> parser!('src: ()) {
+    just(x).padded_by(whitespace()).ignored()
+}
+
+fn binding<'src, 'a: 'src>(arenas: &'a Arenas<'a>) -> parser!('src: Binding<'a>) {
+    recursive(move |binding| {
+        // a single binding in parentheses is just that binding
+        let tuple = binding
+            .separated_by(op(","))
+            .at_least(1)
+            .allow_trailing()
+            .collect::<Vec<_>>()
+            .delimited_by(op("("), op(")"))
+            .map(move |elements| match elements[..] {
+                [element] => element,
+                _ => Binding::Tuple(arenas.patterns.alloc_slice(elements)),
+            });
+        choice((
+            ident().map(Binding::Name),
+            kw_wildcard().to(Binding::Wildcard),
+            kw_true().to(Binding::Bool(true)),
+            kw_false().to(Binding::Bool(false)),
+            number(arenas).map(Binding::Number),
+            tuple,
+        ))
+        .labelled("binding")
+    })
+}
+
+/// The arguments of a lambda, where `(x, y)` is `x y` and `((x, y))` takes a tuple apart
+fn lambda_args<'src, 'a: 'src>(arenas: &'a Arenas<'a>) -> parser!('src: Vec<Binding<'a>>) {
+    let separated = binding(arenas)
+        .separated_by(op(","))
+        .at_least(2)
+        .allow_trailing()
+        .collect::<Vec<_>>()
+        .delimited_by(op("("), op(")"));
+    choice((separated, binding(arenas).map(|arg| vec![arg])))
+        .repeated()
+        .at_least(1)
+        .collect::<Vec<_>>()
+        .map(|args| args.concat())
 }
 
-fn binding<'a>() -> parser!('a: Binding<'a>) {
-    ident().map(Binding).labelled("binding")
+/// A symbol made of operator characters, that isn't part of other syntax like `->` or comments
+fn operator<'src>() -> parser!('src: Operator) {
+    choice((just("//"), just("/*")))
+        .not()
+        .ignore_then(any().filter(|c: &char| "+-*/%<>=!&|^~?@$#".contains(*c)))
+        .repeated()
+        .at_least(1)
+        .to_slice()
+        .filter(|&s| !matches!(s, "=" | "->" | "<-"))
+        .map(|s| Operator::Symbol(Ident::new(s)))
+        .padded_by(whitespace())
+        .labelled("operator")
 }
 
-fn global_symbol<'a>() -> parser!('a: GlobalSymbol<'a>) {
+fn global_symbol<'src>() -> parser!('src: GlobalSymbol) {
     ident().map(GlobalSymbol).labelled("global symbol")
 }
 
-fn expression<'a>() -> parser!('a: Expr<'a>) {
-    recursive(|expression| {
-        let lambda = binding()
+fn expression<'src, 'a: 'src>(arenas: &'a Arenas<'a>) -> parser!('src: &'a Expr<'a>) {
+    let alloc = |expr| arenas.level0.alloc(expr);
+    recursive(move |expression| {
+        let lambda = lambda_args(arenas)
             .then_ignore(op("->"))
             .then(expression.clone())
-            .map(|(arg, body)| Expr::LambdaFunction {
-                arg,
-                body: Box::new(body),
+            .map(move |(args, body)| {
+                let args = arenas.patterns.alloc_slice(args);
+                alloc(Expr::LambdaFunction {
+                    args,
+                    body,
+                    notation: Notation::Prefix,
+                })
             });
         let let_binding = kw_let()
-            .ignore_then(binding())
+            .ignore_then(binding(arenas))
             .then_ignore(op("="))
             .then(expression.clone())
             .then_ignore(kw_in())
             .then(expression.clone())
-            .map(|((name, value), body)| Expr::LetBinding {
-                name,
-                value: Box::new(value),
-                body: Box::new(body),
+            .map(move |((name, value), body)| {
+                alloc(Expr::LetBinding {
+                    name,
+                    value,
+                    body,
+                    notation: Notation::Prefix,
+                })
             });
-        let parenthesised = expression.clone().delimited_by(op("("), op(")"));
-        let number = number().map(Expr::Number);
-        let proc_call = global_symbol()
-            .then(
-                expression
-                    .clone()
-                    .separated_by(op(","))
-                    .allow_trailing()
-                    .collect::<Vec<_>>()
-                    .delimited_by(op("!("), op(")")),
+        let let_rec = kw_let()
+            .ignore_then(kw_rec())
+            .ignore_then(
+                ident()
+                    .map(Binding::Name)
+                    .then_ignore(op("="))
+                    .then(expression.clone())
+                    .separated_by(kw_and())
+                    .at_least(1)
+                    .collect::<Vec<_>>(),
             )
-            .map(|(name, args)| Expr::ProcCall { name, args });
-        let referal = ident().map(Expr::Referal);
+            .then_ignore(kw_in())
+            .then(expression.clone())
+            .map(move |(bindings, body)| {
+                let bindings = arenas.rec_bindings.alloc_slice(bindings);
+                alloc(Expr::LetRec { bindings, body })
+            });
+        let if_then_else = kw_if()
+            .ignore_then(expression.clone())
+            .then_ignore(kw_then())
+            .then(expression.clone())
+            .then_ignore(kw_else())
+            .then(expression.clone())
+            .map(move |((condition, then), otherwise)| {
+                alloc(Expr::If {
+                    condition,
+                    then,
+                    otherwise,
+                })
+            });
+        let boolean =
+            choice((kw_true().to(true), kw_false().to(false))).map(move |b| alloc(Expr::Bool(b)));
+        // an expression in parentheses is just that expression
+        let parenthesised = expression
+            .clone()
+            .separated_by(op(","))
+            .at_least(1)
+            .allow_trailing()
+            .collect::<Vec<_>>()
+            .delimited_by(op("("), op(")"))
+            .map(move |elements: Vec<&'a Expr<'a>>| match elements[..] {
+                [element] => element,
+                _ => {
+                    let elements = arenas.level0.alloc_slice(elements.into_iter().copied());
+                    alloc(Expr::Tuple(elements))
+                }
+            });
+        // tried after the parenthesised expressions, so `(- 1)` is a negation
+        let section = choice((
+            operator()
+                .then(expression.clone().or_not())
+                .map(|(operator, rhs)| (None, operator, rhs)),
+            expression
+                .clone()
+                .then(operator())
+                .map(|(lhs, operator)| (Some(lhs), operator, None)),
+        ))
+        .delimited_by(op("("), op(")"))
+        .map(move |(lhs, operator, rhs)| {
+            let Operator::Symbol(operator) = operator else {
+                unreachable!("operators have symbols")
+            };
+            alloc(Expr::Section { operator, lhs, rhs })
+        });
+        let list = expression
+            .clone()
+            .separated_by(op(","))
+            .allow_trailing()
+            .collect::<Vec<_>>()
+            .delimited_by(op("["), op("]"))
+            .map(move |elements: Vec<&'a Expr<'a>>| {
+                alloc(Expr::List(
+                    arenas.level0.alloc_slice(elements.into_iter().copied()),
+                ))
+            });
+        let fields = ident()
+            .then_ignore(op("="))
+            .then(expression.clone())
+            .separated_by(op(","))
+            .at_least(1)
+            .allow_trailing()
+            .collect::<Vec<_>>()
+            .map(move |fields| arenas.fields.alloc_slice(fields));
+        let record = fields
+            .clone()
+            .delimited_by(op("{"), op("}"))
+            .map(move |fields| alloc(Expr::Record(fields)));
+        let update = expression
+            .clone()
+            .then_ignore(kw_with())
+            .then(fields)
+            .delimited_by(op("{"), op("}"))
+            .map(move |(record, fields)| alloc(Expr::Update { record, fields }));
+        let number = number(arenas).map(move |n| alloc(Expr::Number(n)));
+        let string = string().map(move |s| alloc(Expr::Str(arenas.strings.alloc(s))));
+        let character = character().map(move |c| alloc(Expr::Char(c)));
+        let call_args = expression
+            .clone()
+            .separated_by(op(","))
+            .allow_trailing()
+            .collect::<Vec<_>>()
+            .delimited_by(op("!("), op(")"));
+        let proc_call =
+            global_symbol()
+                .then(call_args.clone())
+                .map(move |(name, args): (_, Vec<_>)| {
+                    let args = arenas.level0.alloc_slice(args.into_iter().copied());
+                    alloc(Expr::ProcCall {
+                        name,
+                        args,
+                        notation: Notation::Prefix,
+                    })
+                });
+        let referal = ident().map(move |name| alloc(Expr::Referal(name)));
 
+        // every level is boxed, otherwise the type of the parser doubles with each one
         let expr = choice((
+            let_rec,
             let_binding,
+            if_then_else,
             lambda,
             parenthesised,
+            section,
+            list,
+            record,
+            update,
             proc_call,
             number,
+            string,
+            character,
+            boolean,
             referal,
         ))
-        .padded();
-        let expr = expr
+        .padded_by(whitespace())
+        .boxed();
+        // a method call passes the receiver as the first argument, `a.f!(b)` is `f!(a, b)`
+        let method_call = global_symbol()
+            .then(call_args)
+            .map(|(name, args)| Suffix::Method(name, args));
+        // after a dot, so `f [1]` stays a call
+        let index = expression
             .clone()
-            .foldl(op("*").ignore_then(expr).repeated(), |lhs, rhs| {
-                Expr::BinaryOperation(Box::new(lhs), BinaryOpKind::Multiplication, Box::new(rhs))
-            });
+            .delimited_by(op("["), op("]"))
+            .map(Suffix::Index);
+        // tried after method calls, which start with a name too
+        let field = ident().map(Suffix::Field);
+        let suffix = op(".").ignore_then(choice((method_call, index, field)));
         let expr = expr
-            .clone()
-            .foldl(op("+").ignore_then(expr).repeated(), |lhs, rhs| {
-                Expr::BinaryOperation(Box::new(lhs), BinaryOpKind::Addition, Box::new(rhs))
-            });
-
+            .foldl(suffix.repeated(), move |receiver, suffix| match suffix {
+                Suffix::Method(name, args) => {
+                    let args = std::iter::once(receiver).chain(args).copied();
+                    alloc(Expr::ProcCall {
+                        name,
+                        args: arenas.level0.alloc_slice(args),
+                        notation: Notation::Postfix,
+                    })
+                }
+                Suffix::Index(index) => alloc(Expr::Index {
+                    list: receiver,
+                    index,
+                }),
+                Suffix::Field(field) => alloc(Expr::Field {
+                    record: receiver,
+                    field,
+                }),
+            })
+            .boxed();
+        // a negative number is a literal, so that i32::MIN can be written
+        let expr = choice((
+            expr.clone(),
+            op("-")
+                .repeated()
+                .at_least(1)
+                .foldr(expr, move |(), expr| alloc(Expr::Negation(expr))),
+        ))
+        .boxed();
+        // operators are grouped when lowering, as their fixities can be declared by the program
+        let chain = choice((
+            operator().then(expr.clone()),
+            expr.clone()
+                .map(|operand| (Operator::Juxtaposition, operand)),
+        ));
         let expr = expr
-            .clone()
-            .foldl(op("&").ignore_then(expr).repeated(), |lhs, rhs| {
-                Expr::BinaryOperation(Box::new(lhs), BinaryOpKind::Composition, Box::new(rhs))
-            });
+            .then(chain.repeated().collect::<Vec<_>>())
+            .map(move |(first, rest)| {
+                if rest.is_empty() {
+                    return first;
+                }
+                let (operators, operands): (Vec<_>, Vec<_>) = rest.into_iter().unzip();
+                let operands = std::iter::once(first).chain(operands).copied();
+                alloc(Expr::Infix {
+                    operands: arenas.level0.alloc_slice(operands),
+                    operators: arenas.operators.alloc_slice(operators),
+                })
+            })
+            .boxed();
 
+        // the postfix forms of lambdas and let bindings, their bodies extend to the left
+        let postfix = choice((
+            op("<-")
+                .ignore_then(lambda_args(arenas))
+                .map(Postfix::Lambda),
+            kw_where()
+                .ignore_then(binding(arenas))
+                .then_ignore(op("="))
+                .then(expr.clone())
+                .map(|(name, value)| Postfix::Where(name, value)),
+        ));
         let expr = expr
-            .clone()
-            .foldl(op(">").ignore_then(expr).repeated(), |lhs, rhs| {
-                Expr::BinaryOperation(Box::new(rhs), BinaryOpKind::Call, Box::new(lhs))
-            });
-
-        let expr = expr.clone().foldl(expr.repeated(), |lhs, rhs| {
-            Expr::BinaryOperation(Box::new(lhs), BinaryOpKind::Call, Box::new(rhs))
-        });
+            .foldl(postfix.repeated(), move |body, postfix| match postfix {
+                Postfix::Lambda(args) => alloc(Expr::LambdaFunction {
+                    args: arenas.patterns.alloc_slice(args),
+                    body,
+                    notation: Notation::Postfix,
+                }),
+                Postfix::Where(name, value) => alloc(Expr::LetBinding {
+                    name,
+                    value,
+                    body,
+                    notation: Notation::Postfix,
+                }),
+            })
+            .boxed();
 
-        expr.padded().labelled("expression")
+        expr.padded_by(whitespace()).labelled("expression")
     })
 }
 
-fn r#type<'a>() -> parser!('a: Type) {
-    let never = just("!").map(|_| Type::Never);
-    let unit = just("()").map(|_| Type::Unit);
-    let i32 = just("i32").map(|_| Type::I32);
-    choice((never, unit, i32)).padded().labelled("type")
+fn r#type<'src>() -> parser!('src: Type) {
+    recursive(|r#type| {
+        let never = just("!").map(|_| Type::Never);
+        let unit = just("()").map(|_| Type::Unit);
+        // any other name is a record
+        let named = ident().map(|name| match name.as_str() {
+            "i32" => Type::I32,
+            "bool" => Type::Bool,
+            "str" => Type::Str,
+            "char" => Type::Char,
+            _ => Type::Named(name),
+        });
+        let tuple = r#type
+            .clone()
+            .separated_by(op(","))
+            .at_least(2)
+            .allow_trailing()
+            .collect()
+            .delimited_by(op("("), op(")"))
+            .map(Type::Tuple);
+        let list = r#type
+            .delimited_by(op("["), op("]"))
+            .map(|element| Type::List(Box::new(element)));
+        choice((never, unit, named, tuple, list))
+            .padded_by(whitespace())
+            .labelled("type")
+    })
 }
 
-fn top<'a>() -> parser!('a: Top<'a>) {
-    let procedure = kw_proc()
-        .ignore_then(global_symbol())
+fn top<'src, 'a: 'src>(arenas: &'a Arenas<'a>) -> parser!('src: Top<'a>) {
+    let procedure = doc_comment()
+        .then_ignore(kw_proc())
+        .then(global_symbol())
         .then(
-            binding()
+            binding(arenas)
                 .then_ignore(op(":"))
                 .then(r#type())
                 .separated_by(op(","))
@@ -163,18 +651,79 @@ fn top<'a>() -> parser!('a: Top<'a>) {
                 .or_not(),
         )
         .then_ignore(op("{"))
-        .then(expression())
+        .then(expression(arenas))
         .then_ignore(op("}"))
-        .map(|(((name, args), ret), body)| Top::Procedure {
+        .map(|((((doc, name), args), ret), body)| Top::Procedure {
+            doc,
             name,
             args,
             return_type: ret.unwrap_or_default(),
             body,
         });
 
-    choice((procedure,)).padded().labelled("top")
+    // `infixl` and `infixr` start with `infix`, so they're tried first
+    let associativity = choice((
+        kw_infixl().to(Associativity::Left),
+        kw_infixr().to(Associativity::Right),
+        kw_infix().to(Associativity::None),
+    ));
+    let precedence = any()
+        .filter(char::is_ascii_digit)
+        .map(|c: char| c as u8 - b'0')
+        .padded_by(whitespace())
+        .labelled("precedence");
+    let infix = associativity
+        .then(precedence)
+        .then(operator())
+        .then_ignore(op("="))
+        .then(expression(arenas))
+        .map(
+            |(((associativity, precedence), operator), function)| Top::Infix {
+                operator: match operator {
+                    Operator::Symbol(symbol) => symbol,
+                    Operator::Juxtaposition => unreachable!("operators have symbols"),
+                },
+                fixity: Fixity {
+                    precedence,
+                    associativity,
+                },
+                function,
+            },
+        );
+
+    let record = kw_record()
+        .ignore_then(ident())
+        .then(
+            ident()
+                .then_ignore(op(":"))
+                .then(r#type())
+                .separated_by(op(","))
+                .at_least(1)
+                .allow_trailing()
+                .collect::<Vec<_>>()
+                .delimited_by(op("{"), op("}")),
+        )
+        .map(|(name, fields)| Top::Record { name, fields });
+
+    choice((procedure, infix, record))
+        .padded_by(whitespace())
+        .labelled("top")
+}
+
+pub fn parser<'src, 'a: 'src>(arenas: &'a Arenas<'a>) -> parser!('src: InitialLevel<'a>) {
+    // so that a file can start with comments, even if it has no procs
+    whitespace()
+        .ignore_then(top(arenas).repeated().collect::<Vec<_>>())
+        .map(move |tops| InitialLevel { tops, arenas })
 }
 
-pub fn parser<'a>() -> parser!('a: InitialLevel<'a>) {
-    top().repeated().collect::<Vec<_>>().map(Into::into)
+pub fn repl_parser<'src, 'a: 'src>(arenas: &'a Arenas<'a>) -> parser!('src: Line<'a>) {
+    choice((
+        top(arenas)
+            .repeated()
+            .at_least(1)
+            .collect::<Vec<_>>()
+            .map(Line::Definitions),
+        expression(arenas).map(Line::Expression),
+    ))
 }
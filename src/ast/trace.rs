@@ -0,0 +1,764 @@
+//! Small-step call-by-value reduction of the lowered program, printing every step.
+//! Unlike eval, this substitutes arguments into function bodies.
+//! The reduced term is always closed, so substituting values never captures anything.
+//! Unrolling a recursive binding copies its lambdas into themselves though,
+//! so a binder can shadow the same binder outside of it
+use super::eval::{Error, Options, Reason};
+use super::level0::{self, GlobalSymbol};
+use super::level1::{self, BinaryOpKind, Binding, Pattern};
+use super::{Arenas, FinalLevel};
+use crate::common::{Ident, Scope};
+use std::collections::HashMap;
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TraceOptions {
+    pub depth: Option<usize>, // only print steps inside at most this many proc calls
+    pub skip_arithmetic: bool,
+    pub only_in: Option<Ident>, // only print steps inside calls to this proc
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Term {
+    Number(i32),
+    Bool(bool),
+    Str(String),
+    Char(char),
+    Tuple(Vec<Self>),
+    List(Vec<Self>),
+    Record(Vec<(Ident, Self)>),
+    Field {
+        record: Box<Self>,
+        field: Ident,
+        idx: usize,
+    },
+    LambdaFunction {
+        arg: Binding,
+        body: Box<Self>,
+    },
+    LetRec {
+        bindings: Vec<(Binding, Self)>,
+        body: Box<Self>,
+    },
+    BinaryOperation(Box<Self>, BinaryOpKind, Box<Self>),
+    Variable(Scope),
+    ProcCall {
+        name: GlobalSymbol,
+        args: Vec<Self>,
+    },
+    Builtin {
+        builtin: level1::Builtin,
+        args: Vec<Self>,
+    },
+    If {
+        condition: Box<Self>,
+        then: Box<Self>,
+        otherwise: Box<Self>,
+    },
+    Unfolded {
+        // the body of a called proc, printed as just the body
+        name: GlobalSymbol,
+        body: Box<Self>,
+    },
+    Destructure {
+        value: Box<Self>,
+        pattern: Pattern,
+        body: Box<Self>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rule {
+    Beta,
+    Unfold(GlobalSymbol),
+    Arithmetic,
+    Branch,
+    Unroll,
+    Match,
+    Builtin(level1::Builtin),
+    Field,
+}
+
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Beta => write!(f, "beta"),
+            Self::Unfold(name) => write!(f, "unfold {name}"),
+            Self::Arithmetic => write!(f, "arithmetic"),
+            Self::Branch => write!(f, "if"),
+            Self::Unroll => write!(f, "unroll"),
+            Self::Match => write!(f, "match"),
+            Self::Builtin(builtin) => write!(f, "{builtin}"),
+            Self::Field => write!(f, "field"),
+        }
+    }
+}
+
+struct Step {
+    rule: Rule,
+    procs: Vec<GlobalSymbol>, // the procs the step happened in, innermost last.
+                              // A proc is unfolded in its caller
+}
+
+impl<'a> Term {
+    fn new(expr: &level1::Expr<'a>) -> Self {
+        match expr {
+            level1::Expr::Number(n) => Self::Number(*n),
+            level1::Expr::Bool(b) => Self::Bool(*b),
+            level1::Expr::Str(s) => Self::Str(s.to_string()),
+            level1::Expr::Char(c) => Self::Char(*c),
+            level1::Expr::Tuple(elements) => {
+                Self::Tuple(elements.iter().map(|element| Self::new(element)).collect())
+            }
+            level1::Expr::List(elements) => {
+                Self::List(elements.iter().map(|element| Self::new(element)).collect())
+            }
+            level1::Expr::Record(fields) => Self::Record(
+                fields
+                    .iter()
+                    .map(|(field, value)| (*field, Self::new(value)))
+                    .collect(),
+            ),
+            level1::Expr::Field { record, field, idx } => Self::Field {
+                record: Box::new(Self::new(record)),
+                field: *field,
+                idx: *idx,
+            },
+            level1::Expr::LambdaFunction { arg, body, .. } => Self::LambdaFunction {
+                arg: *arg,
+                body: Box::new(Self::new(body)),
+            },
+            level1::Expr::LetRec { bindings, body } => Self::LetRec {
+                bindings: bindings
+                    .iter()
+                    .map(|(binding, value)| (*binding, Self::new(value)))
+                    .collect(),
+                body: Box::new(Self::new(body)),
+            },
+            level1::Expr::BinaryOperation(lhs, kind, rhs) => {
+                Self::BinaryOperation(Box::new(Self::new(lhs)), *kind, Box::new(Self::new(rhs)))
+            }
+            level1::Expr::Referal { scope } => Self::Variable(*scope),
+            level1::Expr::ProcCall { name, args } => Self::ProcCall {
+                name: *name,
+                args: args.iter().map(|arg| Self::new(arg)).collect(),
+            },
+            level1::Expr::Builtin { builtin, args } => Self::Builtin {
+                builtin: *builtin,
+                args: args.iter().map(|arg| Self::new(arg)).collect(),
+            },
+            level1::Expr::If {
+                condition,
+                then,
+                otherwise,
+            } => Self::If {
+                condition: Box::new(Self::new(condition)),
+                then: Box::new(Self::new(then)),
+                otherwise: Box::new(Self::new(otherwise)),
+            },
+            level1::Expr::Destructure {
+                value,
+                pattern,
+                body,
+            } => Self::Destructure {
+                value: Box::new(Self::new(value)),
+                pattern: pattern.clone(),
+                body: Box::new(Self::new(body)),
+            },
+        }
+    }
+
+    /// Adds the parts of the value to the values of the pattern's bindings,
+    /// telling whether it matched
+    fn matches(&self, pattern: &Pattern, values: &mut HashMap<Scope, Self>) -> bool {
+        match (pattern, self) {
+            (Pattern::Binding(binding), _) => {
+                values.insert(binding.scope, self.clone());
+                true
+            }
+            (Pattern::Wildcard, _) => true,
+            (Pattern::Number(n), Self::Number(m)) => n == m,
+            (Pattern::Bool(b), Self::Bool(c)) => b == c,
+            (Pattern::Tuple(patterns), Self::Tuple(elements))
+                if patterns.len() == elements.len() =>
+            {
+                patterns
+                    .iter()
+                    .zip(elements)
+                    .all(|(pattern, element)| element.matches(pattern, values))
+            }
+            _ => false,
+        }
+    }
+
+    /// The values of the variables that aren't bound again by the binders
+    fn unshadowed(
+        values: &HashMap<Scope, Self>,
+        binders: impl IntoIterator<Item = Binding>,
+    ) -> std::borrow::Cow<'_, HashMap<Scope, Self>> {
+        let mut values = std::borrow::Cow::Borrowed(values);
+        for binder in binders {
+            if values.contains_key(&binder.scope) {
+                values.to_mut().remove(&binder.scope);
+            }
+        }
+        values
+    }
+
+    /// Replaces the variables with closed values
+    fn substitute(&mut self, values: &HashMap<Scope, Self>) {
+        match self {
+            Self::Number(_) | Self::Bool(_) | Self::Str(_) | Self::Char(_) => {}
+            Self::Tuple(elements) | Self::List(elements) => {
+                elements.iter_mut().for_each(|e| e.substitute(values))
+            }
+            Self::Record(fields) => fields.iter_mut().for_each(|(_, e)| e.substitute(values)),
+            Self::Field { record, .. } => record.substitute(values),
+            Self::LambdaFunction { arg, body } => {
+                body.substitute(&Self::unshadowed(values, [*arg]));
+            }
+            Self::LetRec { bindings, body } => {
+                let values = Self::unshadowed(values, bindings.iter().map(|(b, _)| *b));
+                for (_, value) in bindings {
+                    value.substitute(&values);
+                }
+                body.substitute(&values);
+            }
+            Self::BinaryOperation(lhs, _, rhs) => {
+                lhs.substitute(values);
+                rhs.substitute(values);
+            }
+            Self::Variable(scope) => {
+                if let Some(value) = values.get(scope) {
+                    *self = value.clone();
+                }
+            }
+            Self::ProcCall { args, .. } | Self::Builtin { args, .. } => {
+                args.iter_mut().for_each(|arg| arg.substitute(values))
+            }
+            Self::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                condition.substitute(values);
+                then.substitute(values);
+                otherwise.substitute(values);
+            }
+            Self::Unfolded { body, .. } => body.substitute(values),
+            Self::Destructure {
+                value,
+                pattern,
+                body,
+            } => {
+                value.substitute(values);
+                body.substitute(&Self::unshadowed(values, pattern.bindings()));
+            }
+        }
+    }
+}
+
+struct Tracer<'a> {
+    globals: HashMap<GlobalSymbol, &'a level1::Top<'a>>,
+    procs: Vec<GlobalSymbol>,
+    limits: Options,
+    steps: u64,
+    deadline: Option<Instant>,
+    depth: usize, // of the subterms being reduced
+}
+
+impl<'a> Tracer<'a> {
+    fn stop(&self, reason: Reason) -> Error {
+        Error {
+            reason,
+            proc: self.procs.last().copied(),
+            steps: self.steps,
+        }
+    }
+
+    /// Counts a step, stopping if a limit is exceeded
+    fn tick(&mut self) -> Result<(), Error> {
+        if self.limits.fuel.is_some_and(|fuel| self.steps >= fuel) {
+            return Err(self.stop(Reason::OutOfFuel));
+        }
+        self.steps += 1;
+        if self.deadline.is_some_and(|d| Instant::now() > d) {
+            return Err(self.stop(Reason::Timeout));
+        }
+        Ok(())
+    }
+
+    /// Performs the leftmost innermost reduction, if the term isn't a value yet.
+    /// Terms nest as deep as the calls that aren't finished, so that's limited like calls
+    fn step(&mut self, term: &mut Term) -> Result<Option<Step>, Error> {
+        if self.limits.max_depth.is_some_and(|max| self.depth >= max) {
+            return Err(self.stop(Reason::TooDeep));
+        }
+        self.depth += 1;
+        let res = stacker::maybe_grow(256 * 1024, 4 * 1024 * 1024, || self.reduce(term));
+        self.depth -= 1;
+        res
+    }
+
+    /// Reduces the first subterm that isn't a value
+    fn step_first<'t>(
+        &mut self,
+        terms: impl IntoIterator<Item = &'t mut Term>,
+    ) -> Result<Option<Step>, Error> {
+        for term in terms {
+            if let Some(step) = self.step(term)? {
+                return Ok(Some(step));
+            }
+        }
+        Ok(None)
+    }
+
+    fn reduce(&mut self, term: &mut Term) -> Result<Option<Step>, Error> {
+        Ok(Some(match term {
+            Term::Number(_)
+            | Term::Bool(_)
+            | Term::Str(_)
+            | Term::Char(_)
+            | Term::LambdaFunction { .. } => return Ok(None),
+            Term::Variable(scope) => panic!("{scope} is free"),
+            Term::Tuple(elements) | Term::List(elements) => return self.step_first(elements),
+            Term::Record(fields) => return self.step_first(fields.iter_mut().map(|(_, e)| e)),
+            Term::Field { record, field, idx } => {
+                if let Some(step) = self.step(record)? {
+                    return Ok(Some(step));
+                }
+                // {x = a, y = b}.y -> b
+                match &mut **record {
+                    Term::Record(fields)
+                        if fields.get(*idx).is_some_and(|(name, _)| name == field) =>
+                    {
+                        *term = fields.swap_remove(*idx).1;
+                    }
+                    _ => return Err(self.stop(Reason::NoField(*field))),
+                }
+                self.happened(Rule::Field)
+            }
+            Term::LetRec { bindings, body } => {
+                // let rec f = v in e -> e[f := let rec f = v in v]
+                let unrolled = bindings
+                    .iter()
+                    .map(|(binding, value)| {
+                        let unrolled = Term::LetRec {
+                            bindings: bindings.clone(),
+                            body: Box::new(value.clone()),
+                        };
+                        (binding.scope, unrolled)
+                    })
+                    .collect();
+                body.substitute(&unrolled);
+                *term = std::mem::replace(&mut **body, Term::Number(0));
+                self.happened(Rule::Unroll)
+            }
+            Term::BinaryOperation(lhs, kind, rhs) => {
+                if let Some(step) = self.step_first([&mut **lhs, &mut **rhs])? {
+                    return Ok(Some(step));
+                }
+                let rule = match (&mut **lhs, kind, &**rhs) {
+                    (Term::LambdaFunction { arg, body }, BinaryOpKind::Call, value) => {
+                        body.substitute(&HashMap::from([(arg.scope, value.clone())]));
+                        *term = std::mem::replace(&mut **body, Term::Number(0));
+                        Rule::Beta
+                    }
+                    (_, BinaryOpKind::Call, _) => return Err(self.stop(Reason::NotAFunction)),
+                    (Term::Number(x), BinaryOpKind::Addition, Term::Number(y)) => {
+                        *term = Term::Number(x.wrapping_add(*y));
+                        Rule::Arithmetic
+                    }
+                    (Term::Number(x), BinaryOpKind::Subtraction, Term::Number(y)) => {
+                        *term = Term::Number(x.wrapping_sub(*y));
+                        Rule::Arithmetic
+                    }
+                    (Term::Number(x), BinaryOpKind::Multiplication, Term::Number(y)) => {
+                        *term = Term::Number(x.wrapping_mul(*y));
+                        Rule::Arithmetic
+                    }
+                    (
+                        Term::Number(_),
+                        BinaryOpKind::Division | BinaryOpKind::Remainder,
+                        Term::Number(0),
+                    ) => return Err(self.stop(Reason::DivisionByZero)),
+                    (Term::Number(x), BinaryOpKind::Division, Term::Number(y)) => {
+                        *term = Term::Number(x.wrapping_div(*y));
+                        Rule::Arithmetic
+                    }
+                    (Term::Number(x), BinaryOpKind::Remainder, Term::Number(y)) => {
+                        *term = Term::Number(x.wrapping_rem(*y));
+                        Rule::Arithmetic
+                    }
+                    (Term::Number(x), kind, Term::Number(y)) => match kind.compare(*x, *y) {
+                        Some(result) => {
+                            *term = Term::Bool(result);
+                            Rule::Arithmetic
+                        }
+                        None => return Err(self.stop(Reason::WrongOperands(*kind))),
+                    },
+                    (Term::Bool(x), BinaryOpKind::Equal, Term::Bool(y)) => {
+                        *term = Term::Bool(*x == *y);
+                        Rule::Arithmetic
+                    }
+                    (Term::Bool(x), BinaryOpKind::NotEqual, Term::Bool(y)) => {
+                        *term = Term::Bool(*x != *y);
+                        Rule::Arithmetic
+                    }
+                    (Term::Str(x), BinaryOpKind::Concatenation, Term::Str(y)) => {
+                        *term = Term::Str(format!("{x}{y}"));
+                        Rule::Arithmetic
+                    }
+                    (Term::Char(x), kind, Term::Char(y)) => {
+                        match kind.compare(*x as i32, *y as i32) {
+                            Some(result) => {
+                                *term = Term::Bool(result);
+                                Rule::Arithmetic
+                            }
+                            None => return Err(self.stop(Reason::WrongOperands(*kind))),
+                        }
+                    }
+                    (_, kind, _) => return Err(self.stop(Reason::WrongOperands(*kind))),
+                };
+                self.happened(rule)
+            }
+            Term::ProcCall { name, args } => {
+                if let Some(step) = self.step_first(args.iter_mut())? {
+                    return Ok(Some(step));
+                }
+                let level1::Top::Procedure {
+                    args: params, body, ..
+                } = self.globals[name];
+                let mut body = Term::new(body);
+                body.substitute(
+                    &params
+                        .iter()
+                        .map(|(binding, _)| binding.scope)
+                        .zip(std::mem::take(args))
+                        .collect(),
+                );
+                let name = *name;
+                *term = Term::Unfolded {
+                    name,
+                    body: Box::new(body),
+                };
+                self.happened(Rule::Unfold(name))
+            }
+            Term::Builtin { builtin, args } => {
+                if let Some(step) = self.step_first(args.iter_mut())? {
+                    return Ok(Some(step));
+                }
+                let call = |function: &Term, arg| {
+                    Term::BinaryOperation(Box::new(function.clone()), BinaryOpKind::Call, arg)
+                };
+                let builtin = *builtin;
+                *term = match (builtin, std::mem::take(args).as_mut_slice()) {
+                    (level1::Builtin::Length, [Term::Str(s)]) => {
+                        Term::Number(s.chars().count() as i32)
+                    }
+                    (level1::Builtin::Length, [Term::List(elements)]) => {
+                        Term::Number(elements.len() as i32)
+                    }
+                    (level1::Builtin::Index, [Term::List(elements), Term::Number(idx)]) => {
+                        match usize::try_from(*idx).ok().filter(|&i| i < elements.len()) {
+                            Some(idx) => elements.swap_remove(idx),
+                            None => return Err(self.stop(Reason::OutOfBounds)),
+                        }
+                    }
+                    // map!([a, b], f) -> [f a, f b]
+                    (level1::Builtin::Map, [Term::List(elements), function]) => Term::List(
+                        std::mem::take(elements)
+                            .into_iter()
+                            .map(|element| call(function, Box::new(element)))
+                            .collect(),
+                    ),
+                    // fold!([a, b], init, f) -> f (f init a) b
+                    (level1::Builtin::Fold, [Term::List(elements), init, function]) => {
+                        let init = std::mem::replace(init, Term::Number(0));
+                        std::mem::take(elements)
+                            .into_iter()
+                            .fold(init, |acc, element| {
+                                let partial = call(function, Box::new(acc));
+                                call(&partial, Box::new(element))
+                            })
+                    }
+                    (level1::Builtin::Push, [Term::List(elements), new]) => {
+                        let mut elements = std::mem::take(elements);
+                        elements.push(std::mem::replace(new, Term::Number(0)));
+                        Term::List(elements)
+                    }
+                    _ => return Err(self.stop(Reason::WrongArguments(builtin))),
+                };
+                self.happened(Rule::Builtin(builtin))
+            }
+            Term::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                if let Some(step) = self.step(condition)? {
+                    return Ok(Some(step));
+                }
+                // the branch that isn't taken is dropped without being reduced
+                let taken = match **condition {
+                    Term::Bool(true) => then,
+                    Term::Bool(false) => otherwise,
+                    _ => return Err(self.stop(Reason::NotABool)),
+                };
+                *term = std::mem::replace(&mut **taken, Term::Number(0));
+                self.happened(Rule::Branch)
+            }
+            Term::Unfolded { name, body } => {
+                self.procs.push(*name);
+                let step = self.step(body);
+                self.procs.pop();
+                if let Ok(None) = step {
+                    // the call returned
+                    *term = std::mem::replace(&mut **body, Term::Number(0));
+                }
+                return step;
+            }
+            Term::Destructure {
+                value,
+                pattern,
+                body,
+            } => {
+                if let Some(step) = self.step(value)? {
+                    return Ok(Some(step));
+                }
+                let mut values = HashMap::new();
+                if !value.matches(pattern, &mut values) {
+                    return Err(self.stop(Reason::NoMatch));
+                }
+                body.substitute(&values);
+                *term = std::mem::replace(&mut **body, Term::Number(0));
+                self.happened(Rule::Match)
+            }
+        }))
+    }
+
+    fn happened(&self, rule: Rule) -> Step {
+        Step {
+            rule,
+            procs: self.procs.clone(),
+        }
+    }
+}
+
+/// Turns a term back into source syntax, with the source names of the bindings
+struct Resugarer<'a, 'b> {
+    names: &'b HashMap<Scope, Ident>,
+    arenas: &'a Arenas<'a>,
+    bound: Vec<(Scope, Ident)>,
+}
+
+impl<'a> Resugarer<'a, '_> {
+    fn name(&self, scope: Scope) -> Ident {
+        let Some(&(_, name)) = self.bound.iter().rev().find(|(s, _)| *s == scope) else {
+            panic!("{scope} is free")
+        };
+        name
+    }
+
+    /// The pattern with fresh names, which are bound until they're popped
+    fn resugar_pattern(&mut self, pattern: &Pattern, used: &[Ident]) -> level0::Binding<'a> {
+        match pattern {
+            Pattern::Binding(binding) => {
+                let name = self
+                    .names
+                    .get(&binding.scope)
+                    .copied()
+                    .unwrap_or(Ident::new("x"))
+                    .fresh(used);
+                self.bound.push((binding.scope, name));
+                level0::Binding::Name(name)
+            }
+            Pattern::Wildcard => level0::Binding::Wildcard,
+            Pattern::Number(n) => level0::Binding::Number((*n).into()),
+            Pattern::Bool(b) => level0::Binding::Bool(*b),
+            Pattern::Tuple(patterns) => {
+                let patterns = patterns
+                    .iter()
+                    .map(|pattern| self.resugar_pattern(pattern, used))
+                    .collect::<Vec<_>>();
+                level0::Binding::Tuple(self.arenas.patterns.alloc_slice(patterns))
+            }
+        }
+    }
+
+    fn resugar_ref(&mut self, term: &Term) -> &'a level0::Expr<'a> {
+        self.arenas.level0.alloc(self.resugar(term))
+    }
+
+    // terms get as deep as the calls, so it grows the stack like step does
+    fn resugar(&mut self, term: &Term) -> level0::Expr<'a> {
+        stacker::maybe_grow(256 * 1024, 4 * 1024 * 1024, || self.resugar_now(term))
+    }
+
+    fn resugar_now(&mut self, term: &Term) -> level0::Expr<'a> {
+        match term {
+            Term::Number(n) => level0::Expr::Number((*n).into()),
+            Term::Bool(b) => level0::Expr::Bool(*b),
+            Term::Str(s) => level0::Expr::Str(self.arenas.strings.alloc(s.clone())),
+            Term::Char(c) => level0::Expr::Char(*c),
+            Term::Tuple(elements) => {
+                let elements = elements.iter().map(|e| self.resugar(e)).collect::<Vec<_>>();
+                level0::Expr::Tuple(self.arenas.level0.alloc_slice(elements))
+            }
+            Term::List(elements) => {
+                let elements = elements.iter().map(|e| self.resugar(e)).collect::<Vec<_>>();
+                level0::Expr::List(self.arenas.level0.alloc_slice(elements))
+            }
+            Term::Record(fields) => {
+                let fields = fields
+                    .iter()
+                    .map(|(field, value)| (*field, self.resugar_ref(value)))
+                    .collect::<Vec<_>>();
+                level0::Expr::Record(self.arenas.fields.alloc_slice(fields))
+            }
+            Term::Field { record, field, .. } => level0::Expr::Field {
+                record: self.resugar_ref(record),
+                field: *field,
+            },
+            Term::LambdaFunction { arg, body } => {
+                let used = self.bound.iter().map(|(_, name)| *name).collect::<Vec<_>>();
+                let start = self.bound.len();
+                // an argument that was a pattern is printed as one
+                let (pattern, body) = match &**body {
+                    Term::Destructure {
+                        value,
+                        pattern,
+                        body,
+                    } if **value == Term::Variable(arg.scope)
+                        && !self.names.contains_key(&arg.scope) =>
+                    {
+                        (self.resugar_pattern(pattern, &used), body)
+                    }
+                    _ => (self.resugar_pattern(&Pattern::Binding(*arg), &used), body),
+                };
+                let body = self.resugar_ref(body);
+                self.bound.truncate(start);
+                level0::Expr::LambdaFunction {
+                    args: self.arenas.patterns.alloc_slice([pattern]),
+                    body,
+                    notation: level0::Notation::Prefix,
+                }
+            }
+            Term::LetRec { bindings, body } => {
+                let used = self.bound.iter().map(|(_, name)| *name).collect::<Vec<_>>();
+                for (binding, _) in bindings {
+                    let name = self
+                        .names
+                        .get(&binding.scope)
+                        .copied()
+                        .unwrap_or(Ident::new("f"))
+                        .fresh(&used);
+                    self.bound.push((binding.scope, name));
+                }
+                let resugared = bindings
+                    .iter()
+                    .map(|(binding, value)| {
+                        let name = self.name(binding.scope);
+                        (level0::Binding::Name(name), self.resugar_ref(value))
+                    })
+                    .collect::<Vec<_>>();
+                let body = self.resugar_ref(body);
+                self.bound.truncate(self.bound.len() - bindings.len());
+                level0::Expr::LetRec {
+                    bindings: self.arenas.rec_bindings.alloc_slice(resugared),
+                    body,
+                }
+            }
+            Term::BinaryOperation(lhs, kind, rhs) => level0::Expr::BinaryOperation(
+                self.resugar_ref(lhs),
+                (*kind).into(),
+                self.resugar_ref(rhs),
+            ),
+            Term::Variable(scope) => level0::Expr::Referal(self.name(*scope)),
+            Term::ProcCall { name, args } => {
+                let args = args.iter().map(|arg| self.resugar(arg)).collect::<Vec<_>>();
+                level0::Expr::ProcCall {
+                    name: *name,
+                    args: self.arenas.level0.alloc_slice(args),
+                    notation: level0::Notation::Prefix,
+                }
+            }
+            Term::Builtin { builtin, args } => {
+                let args = args.iter().map(|arg| self.resugar(arg)).collect::<Vec<_>>();
+                builtin.resugar(self.arenas.level0.alloc_slice(args))
+            }
+            Term::If {
+                condition,
+                then,
+                otherwise,
+            } => level0::Expr::If {
+                condition: self.resugar_ref(condition),
+                then: self.resugar_ref(then),
+                otherwise: self.resugar_ref(otherwise),
+            },
+            Term::Unfolded { body, .. } => self.resugar(body),
+            Term::Destructure {
+                value,
+                pattern,
+                body,
+            } => {
+                let value = self.resugar_ref(value);
+                let used = self.bound.iter().map(|(_, name)| *name).collect::<Vec<_>>();
+                let start = self.bound.len();
+                let name = self.resugar_pattern(pattern, &used);
+                let body = self.resugar_ref(body);
+                self.bound.truncate(start);
+                level0::Expr::LetBinding {
+                    name,
+                    value,
+                    body,
+                    notation: level0::Notation::Prefix,
+                }
+            }
+        }
+    }
+}
+
+impl<'a> FinalLevel<'a> {
+    /// Reduces main step by step, printing the term after every step.
+    /// It stops like eval does, but only the limits of the options matter, as it's always strict
+    pub fn trace(&self, options: TraceOptions, limits: Options) -> Result<(), Error> {
+        let mut tracer = Tracer {
+            globals: self
+                .tops
+                .iter()
+                .map(|top| match top {
+                    level1::Top::Procedure { name, .. } => (*name, *top),
+                })
+                .collect(),
+            procs: vec![],
+            limits,
+            steps: 0,
+            deadline: limits.timeout.map(|timeout| Instant::now() + timeout),
+            depth: 0,
+        };
+        let mut resugarer = Resugarer {
+            names: &self.names,
+            arenas: self.arenas,
+            bound: vec![],
+        };
+        let mut term = Term::ProcCall {
+            name: GlobalSymbol(Ident::new("main")),
+            args: vec![],
+        };
+        println!("   {}", resugarer.resugar(&term));
+        while let Some(step) = tracer.step(&mut term)? {
+            tracer.tick()?;
+            let shown = !(options.skip_arithmetic && step.rule == Rule::Arithmetic)
+                && options.depth.is_none_or(|depth| step.procs.len() <= depth)
+                && options
+                    .only_in
+                    .is_none_or(|name| step.procs.contains(&GlobalSymbol(name)));
+            if shown {
+                println!("=> {}   [{}]", resugarer.resugar(&term), step.rule);
+            }
+        }
+        println!("{}", resugarer.resugar(&term));
+        Ok(())
+    }
+}
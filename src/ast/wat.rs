@@ -0,0 +1,587 @@
+//! Emits a WebAssembly text module from the lowered program.
+//! Every value is an i32: numbers are just numbers, which wrap around on overflow, bools are
+//! 0 or 1 and functions are pointers to closure records `[table index, captures...]` in linear
+//! memory.
+//! The captures of a record are sorted by their scope.
+//! Tuples are pointers to records of their elements, a pattern that doesn't match traps.
+//! Records are laid out like tuples, with the fields in the order they're declared.
+//! Chars are their codes, strings and lists are pointers to records `[length, codes...]`
+//! and `[length, elements...]`, indexing past the end traps
+use super::FinalLevel;
+use super::level1::{BinaryOpKind, Binding, Builtin, Expr, Pattern, Top};
+use std::collections::HashSet;
+
+const RUNTIME: &str = r#"  (type $closure (func (param i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (global $rt/heap (mut i32) (i32.const 0))
+  (func $rt/alloc (param $size i32) (result i32)
+    (local $ptr i32)
+    global.get $rt/heap
+    local.set $ptr
+    global.get $rt/heap
+    local.get $size
+    i32.add
+    global.set $rt/heap
+    block
+      global.get $rt/heap
+      memory.size
+      i32.const 16
+      i32.shl
+      i32.le_u
+      br_if 0
+      global.get $rt/heap
+      memory.size
+      i32.const 16
+      i32.shl
+      i32.sub
+      i32.const 16
+      i32.shr_u
+      i32.const 1
+      i32.add
+      memory.grow
+      drop
+    end
+    local.get $ptr)
+  (func $rt/div (param $a i32) (param $b i32) (result i32)
+    local.get $b
+    i32.const -1
+    i32.eq
+    if (result i32)
+      i32.const 0
+      local.get $a
+      i32.sub
+    else
+      local.get $a
+      local.get $b
+      i32.div_s
+    end)
+  (func $rt/concat (param $a i32) (param $b i32) (result i32)
+    (local $len i32)
+    (local $ptr i32)
+    local.get $a
+    i32.load
+    local.get $b
+    i32.load
+    i32.add
+    local.set $len
+    local.get $len
+    i32.const 1
+    i32.add
+    i32.const 2
+    i32.shl
+    call $rt/alloc
+    local.set $ptr
+    local.get $ptr
+    local.get $len
+    i32.store
+    local.get $ptr
+    i32.const 4
+    i32.add
+    local.get $a
+    i32.const 4
+    i32.add
+    local.get $a
+    i32.load
+    i32.const 2
+    i32.shl
+    memory.copy
+    local.get $ptr
+    i32.const 4
+    i32.add
+    local.get $a
+    i32.load
+    i32.const 2
+    i32.shl
+    i32.add
+    local.get $b
+    i32.const 4
+    i32.add
+    local.get $b
+    i32.load
+    i32.const 2
+    i32.shl
+    memory.copy
+    local.get $ptr)
+  (func $rt/index (param $list i32) (param $idx i32) (result i32)
+    local.get $idx
+    local.get $list
+    i32.load
+    i32.ge_u
+    if
+      unreachable
+    end
+    local.get $list
+    local.get $idx
+    i32.const 2
+    i32.shl
+    i32.add
+    i32.load offset=4)
+  (func $rt/map (param $list i32) (param $f i32) (result i32)
+    (local $len i32)
+    (local $new i32)
+    (local $idx i32)
+    local.get $list
+    i32.load
+    local.set $len
+    local.get $len
+    i32.const 1
+    i32.add
+    i32.const 2
+    i32.shl
+    call $rt/alloc
+    local.tee $new
+    local.get $len
+    i32.store
+    block
+      loop
+        local.get $idx
+        local.get $len
+        i32.ge_u
+        br_if 1
+        local.get $new
+        local.get $idx
+        i32.const 2
+        i32.shl
+        i32.add
+        local.get $f
+        local.get $list
+        local.get $idx
+        i32.const 2
+        i32.shl
+        i32.add
+        i32.load offset=4
+        local.get $f
+        i32.load
+        call_indirect (type $closure)
+        i32.store offset=4
+        local.get $idx
+        i32.const 1
+        i32.add
+        local.set $idx
+        br 0
+      end
+    end
+    local.get $new)
+  (func $rt/fold (param $list i32) (param $acc i32) (param $f i32) (result i32)
+    (local $idx i32)
+    (local $partial i32)
+    block
+      loop
+        local.get $idx
+        local.get $list
+        i32.load
+        i32.ge_u
+        br_if 1
+        local.get $f
+        local.get $acc
+        local.get $f
+        i32.load
+        call_indirect (type $closure)
+        local.tee $partial
+        local.get $list
+        local.get $idx
+        i32.const 2
+        i32.shl
+        i32.add
+        i32.load offset=4
+        local.get $partial
+        i32.load
+        call_indirect (type $closure)
+        local.set $acc
+        local.get $idx
+        i32.const 1
+        i32.add
+        local.set $idx
+        br 0
+      end
+    end
+    local.get $acc)
+  (func $rt/push (param $list i32) (param $new i32) (result i32)
+    (local $len i32)
+    (local $ptr i32)
+    local.get $list
+    i32.load
+    local.set $len
+    local.get $len
+    i32.const 2
+    i32.add
+    i32.const 2
+    i32.shl
+    call $rt/alloc
+    local.tee $ptr
+    local.get $len
+    i32.const 1
+    i32.add
+    i32.store
+    local.get $ptr
+    i32.const 4
+    i32.add
+    local.get $list
+    i32.const 4
+    i32.add
+    local.get $len
+    i32.const 2
+    i32.shl
+    memory.copy
+    local.get $ptr
+    local.get $len
+    i32.const 2
+    i32.shl
+    i32.add
+    local.get $new
+    i32.store offset=4
+    local.get $ptr)
+"#;
+
+#[derive(Debug, Default)]
+struct Module {
+    lambdas: Vec<String>, // indexed by their position in the table
+}
+
+struct Function<'m> {
+    module: &'m mut Module,
+    env: Vec<Binding>,    // the captures of the closure record in $env
+    locals: Vec<Binding>, // the recursive and destructured bindings, which aren't parameters
+    temps: usize,
+    code: Vec<String>,
+}
+
+impl Module {
+    fn lambda(&mut self, arg: Binding, captured: Vec<Binding>, body: &Expr) -> usize {
+        let idx = self.lambdas.len();
+        self.lambdas.push(String::new()); // reserve the index for nested lambdas
+        let mut fun = Function::new(self, captured);
+        fun.expr(body);
+        self.lambdas[idx] = fun.finish(
+            &format!("$lambda/{idx}"),
+            &format!(" (param $env i32) (param ${arg} i32)"),
+        );
+        idx
+    }
+
+    fn procedure(&mut self, top: &Top) -> String {
+        match top {
+            Top::Procedure {
+                name, args, body, ..
+            } => {
+                let mut fun = Function::new(self, vec![]);
+                fun.expr(body);
+                let params = args
+                    .iter()
+                    .map(|(binding, _)| format!(" (param ${binding} i32)"))
+                    .collect::<String>();
+                fun.finish(&format!("${name}"), &params)
+            }
+        }
+    }
+}
+
+impl<'m> Function<'m> {
+    fn new(module: &'m mut Module, env: Vec<Binding>) -> Self {
+        Self {
+            module,
+            env,
+            locals: vec![],
+            temps: 0,
+            code: vec![],
+        }
+    }
+
+    fn emit(&mut self, instr: impl Into<String>) {
+        self.code.push(instr.into());
+    }
+
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("$tmp/{}", self.temps - 1)
+    }
+
+    fn finish(self, name: &str, params: &str) -> String {
+        let mut out = format!("  (func {name}{params} (result i32)\n");
+        for binding in self.locals {
+            out += &format!("    (local ${binding} i32)\n");
+        }
+        for idx in 0..self.temps {
+            out += &format!("    (local $tmp/{idx} i32)\n");
+        }
+        for instr in self.code {
+            out += &format!("    {instr}\n");
+        }
+        out.pop();
+        out + ")\n"
+    }
+
+    fn binding(&mut self, binding: Binding) {
+        match self.env.iter().position(|&b| b == binding) {
+            Some(idx) => {
+                self.emit("local.get $env");
+                self.emit(format!("i32.load offset={}", 4 * (idx + 1)));
+            }
+            None => self.emit(format!("local.get ${binding}")),
+        }
+    }
+
+    fn comparison(&mut self, lhs: &Expr, rhs: &Expr, instr: &str) {
+        self.expr(lhs);
+        self.expr(rhs);
+        self.emit(instr);
+    }
+
+    /// Allocates the record of a closure, putting its address in the local
+    fn alloc_closure(&mut self, captured: &HashSet<Binding>, local: &str) {
+        self.emit(format!("i32.const {}", 4 * (captured.len() + 1)));
+        self.emit("call $rt/alloc");
+        self.emit(format!("local.set {local}"));
+    }
+
+    /// Stores the table index and the captures of the lambda in the record in the local
+    fn fill_closure(&mut self, lambda: &Expr, local: &str) {
+        let Expr::LambdaFunction {
+            arg,
+            body,
+            captured,
+        } = lambda
+        else {
+            unreachable!()
+        };
+        let mut captured = captured.iter().copied().collect::<Vec<_>>();
+        captured.sort_by_key(|b| b.scope);
+        for (idx, &binding) in captured.iter().enumerate() {
+            self.emit(format!("local.get {local}"));
+            self.binding(binding);
+            self.emit(format!("i32.store offset={}", 4 * (idx + 1)));
+        }
+        let idx = self.module.lambda(*arg, captured, body);
+        self.emit(format!("local.get {local}"));
+        self.emit(format!("i32.const {idx}"));
+        self.emit("i32.store");
+    }
+
+    /// Matches the value on the stack against the pattern, putting its parts in locals
+    fn pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Binding(binding) => {
+                self.locals.push(*binding);
+                self.emit(format!("local.set ${binding}"));
+            }
+            Pattern::Wildcard => self.emit("drop"),
+            Pattern::Number(n) => self.mismatch(*n),
+            Pattern::Bool(b) => self.mismatch(i32::from(*b)),
+            Pattern::Tuple(patterns) => {
+                let record = self.temp();
+                self.emit(format!("local.set {record}"));
+                for (idx, pattern) in patterns.iter().enumerate() {
+                    self.emit(format!("local.get {record}"));
+                    self.emit(format!("i32.load offset={}", 4 * idx));
+                    self.pattern(pattern);
+                }
+            }
+        }
+    }
+
+    /// Traps unless the value on the stack is the literal
+    fn mismatch(&mut self, literal: i32) {
+        self.emit(format!("i32.const {literal}"));
+        self.emit("i32.ne");
+        self.emit("if");
+        self.emit("unreachable");
+        self.emit("end");
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Number(n) => self.emit(format!("i32.const {n}")),
+            Expr::Bool(b) => self.emit(format!("i32.const {}", i32::from(*b))),
+            Expr::Str(s) => {
+                let record = self.temp();
+                let codes = s.chars().collect::<Vec<_>>();
+                self.emit(format!("i32.const {}", 4 * (codes.len() + 1)));
+                self.emit("call $rt/alloc");
+                self.emit(format!("local.tee {record}"));
+                self.emit(format!("i32.const {}", codes.len()));
+                self.emit("i32.store");
+                for (idx, code) in codes.into_iter().enumerate() {
+                    self.emit(format!("local.get {record}"));
+                    self.emit(format!("i32.const {}", u32::from(code)));
+                    self.emit(format!("i32.store offset={}", 4 * (idx + 1)));
+                }
+                self.emit(format!("local.get {record}"));
+            }
+            Expr::Char(c) => self.emit(format!("i32.const {}", u32::from(*c))),
+            Expr::Tuple(elements) => {
+                let record = self.temp();
+                self.emit(format!("i32.const {}", 4 * elements.len()));
+                self.emit("call $rt/alloc");
+                self.emit(format!("local.set {record}"));
+                for (idx, element) in elements.iter().enumerate() {
+                    self.emit(format!("local.get {record}"));
+                    self.expr(element);
+                    self.emit(format!("i32.store offset={}", 4 * idx));
+                }
+                self.emit(format!("local.get {record}"));
+            }
+            Expr::Record(fields) => {
+                let record = self.temp();
+                self.emit(format!("i32.const {}", 4 * fields.len()));
+                self.emit("call $rt/alloc");
+                self.emit(format!("local.set {record}"));
+                for (idx, (_, value)) in fields.iter().enumerate() {
+                    self.emit(format!("local.get {record}"));
+                    self.expr(value);
+                    self.emit(format!("i32.store offset={}", 4 * idx));
+                }
+                self.emit(format!("local.get {record}"));
+            }
+            Expr::Field { record, idx, .. } => {
+                self.expr(record);
+                self.emit(format!("i32.load offset={}", 4 * idx));
+            }
+            Expr::List(elements) => {
+                let record = self.temp();
+                self.emit(format!("i32.const {}", 4 * (elements.len() + 1)));
+                self.emit("call $rt/alloc");
+                self.emit(format!("local.tee {record}"));
+                self.emit(format!("i32.const {}", elements.len()));
+                self.emit("i32.store");
+                for (idx, element) in elements.iter().enumerate() {
+                    self.emit(format!("local.get {record}"));
+                    self.expr(element);
+                    self.emit(format!("i32.store offset={}", 4 * (idx + 1)));
+                }
+                self.emit(format!("local.get {record}"));
+            }
+            Expr::Referal { scope } => self.binding(Binding { scope: *scope }),
+            Expr::LambdaFunction { captured, .. } => {
+                let record = self.temp();
+                self.alloc_closure(captured, &record);
+                self.fill_closure(expr, &record);
+                self.emit(format!("local.get {record}"));
+            }
+            Expr::LetRec { bindings, body } => {
+                // all the records are made before they're filled, so they can capture each other
+                for (binding, value) in bindings {
+                    let Expr::LambdaFunction { captured, .. } = value else {
+                        unreachable!("the values of recursive bindings are lambdas")
+                    };
+                    self.locals.push(*binding);
+                    self.alloc_closure(captured, &format!("${binding}"));
+                }
+                for (binding, value) in bindings {
+                    self.fill_closure(value, &format!("${binding}"));
+                }
+                self.expr(body);
+            }
+            Expr::BinaryOperation(lhs, kind, rhs) => match kind {
+                BinaryOpKind::Call => {
+                    let closure = self.temp();
+                    self.expr(lhs);
+                    self.emit(format!("local.tee {closure}"));
+                    self.expr(rhs);
+                    self.emit(format!("local.get {closure}"));
+                    self.emit("i32.load");
+                    self.emit("call_indirect (type $closure)");
+                }
+                BinaryOpKind::Addition => {
+                    self.expr(lhs);
+                    self.expr(rhs);
+                    self.emit("i32.add");
+                }
+                BinaryOpKind::Subtraction => {
+                    self.expr(lhs);
+                    self.expr(rhs);
+                    self.emit("i32.sub");
+                }
+                BinaryOpKind::Multiplication => {
+                    self.expr(lhs);
+                    self.expr(rhs);
+                    self.emit("i32.mul");
+                }
+                // these trap when dividing by zero
+                BinaryOpKind::Division => {
+                    self.expr(lhs);
+                    self.expr(rhs);
+                    self.emit("call $rt/div");
+                }
+                BinaryOpKind::Remainder => {
+                    self.expr(lhs);
+                    self.expr(rhs);
+                    self.emit("i32.rem_s");
+                }
+                BinaryOpKind::Concatenation => {
+                    self.expr(lhs);
+                    self.expr(rhs);
+                    self.emit("call $rt/concat");
+                }
+                BinaryOpKind::Equal => self.comparison(lhs, rhs, "i32.eq"),
+                BinaryOpKind::NotEqual => self.comparison(lhs, rhs, "i32.ne"),
+                BinaryOpKind::Less => self.comparison(lhs, rhs, "i32.lt_s"),
+                BinaryOpKind::LessEqual => self.comparison(lhs, rhs, "i32.le_s"),
+                BinaryOpKind::Greater => self.comparison(lhs, rhs, "i32.gt_s"),
+                BinaryOpKind::GreaterEqual => self.comparison(lhs, rhs, "i32.ge_s"),
+            },
+            Expr::ProcCall { name, args } => {
+                for arg in args {
+                    self.expr(arg);
+                }
+                self.emit(format!("call ${name}"));
+            }
+            Expr::Builtin { builtin, args } => {
+                for arg in args {
+                    self.expr(arg);
+                }
+                match builtin {
+                    Builtin::Length => self.emit("i32.load"),
+                    Builtin::Index => self.emit("call $rt/index"),
+                    Builtin::Map => self.emit("call $rt/map"),
+                    Builtin::Fold => self.emit("call $rt/fold"),
+                    Builtin::Push => self.emit("call $rt/push"),
+                }
+            }
+            Expr::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.expr(condition);
+                self.emit("if (result i32)");
+                self.expr(then);
+                self.emit("else");
+                self.expr(otherwise);
+                self.emit("end");
+            }
+            Expr::Destructure {
+                value,
+                pattern,
+                body,
+            } => {
+                self.expr(value);
+                self.pattern(pattern);
+                self.expr(body);
+            }
+        }
+    }
+}
+
+impl FinalLevel<'_> {
+    pub fn emit_wat(&self) -> String {
+        let mut module = Module::default();
+        let procs = self
+            .tops
+            .iter()
+            .map(|top| module.procedure(top))
+            .collect::<Vec<_>>();
+        let mut out = format!("(module\n{RUNTIME}");
+        out += &format!("  (table {} funcref)\n", module.lambdas.len());
+        if !module.lambdas.is_empty() {
+            out += "  (elem (i32.const 0)";
+            for idx in 0..module.lambdas.len() {
+                out += &format!(" $lambda/{idx}");
+            }
+            out += ")\n";
+        }
+        for fun in procs.iter().chain(&module.lambdas) {
+            out += fun;
+        }
+        out + "  (export \"main\" (func $main)))\n"
+    }
+}
@@ -1,8 +1,11 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
 static COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(1);
 fn get_id() -> usize {
     COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
 }
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Id(usize);
 impl Id {
     pub fn new() -> Self {
@@ -10,24 +13,61 @@ impl Id {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Scope(Id);
 impl Scope {
     pub fn new() -> Self {
         Self(Id::new())
     }
 }
+/// Every name of the session, so identifiers don't borrow the text they were parsed from
+#[derive(Default)]
+struct Interner {
+    ids: HashMap<&'static str, usize>,
+    names: Vec<&'static str>, // leaked, as they're used until the end anyway
+}
+static INTERNER: LazyLock<Mutex<Interner>> = LazyLock::new(Mutex::default);
+
 #[derive(Clone, PartialEq, Eq, Copy, Hash)]
-pub struct Ident<'a>(pub &'a str);
+pub struct Ident(usize);
+
+impl Ident {
+    pub fn new(name: &str) -> Self {
+        let mut interner = INTERNER.lock().unwrap();
+        if let Some(&id) = interner.ids.get(name) {
+            return Self(id);
+        }
+        let name: &'static str = Box::leak(name.into());
+        let id = interner.names.len();
+        interner.names.push(name);
+        interner.ids.insert(name, id);
+        Self(id)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        INTERNER.lock().unwrap().names[self.0]
+    }
+
+    /// This name, numbered if it's already used
+    pub fn fresh(self, used: &[Self]) -> Self {
+        if !used.contains(&self) {
+            return self;
+        }
+        (1..)
+            .map(|n| Self::new(&format!("{self}{n}")))
+            .find(|fresh| !used.contains(fresh))
+            .unwrap()
+    }
+}
 
-impl std::fmt::Debug for Ident<'_> {
+impl std::fmt::Debug for Ident {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{self}")
     }
 }
-impl std::fmt::Display for Ident<'_> {
+impl std::fmt::Display for Ident {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.as_str())
     }
 }
 
@@ -36,3 +76,81 @@ impl std::fmt::Display for Scope {
         write!(f, "#{}", self.0.0)
     }
 }
+
+/// Owns values for as long as it lives, handing out shared references to them,
+/// so trees can point at their nodes instead of owning copies of them
+pub struct Arena<T> {
+    chunks: std::cell::RefCell<Vec<Vec<T>>>, // a chunk never grows past its capacity
+}
+
+impl<T> std::fmt::Debug for Arena<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Arena")
+    }
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Self {
+            chunks: std::cell::RefCell::new(vec![Vec::with_capacity(64)]),
+        }
+    }
+}
+
+impl<T> Arena<T> {
+    /// The chunk with space for `len` more values
+    fn chunk_for(chunks: &mut Vec<Vec<T>>, len: usize) -> &mut Vec<T> {
+        let last = chunks.last().unwrap();
+        if last.capacity() - last.len() < len {
+            let capacity = (last.capacity() * 2).max(len);
+            chunks.push(Vec::with_capacity(capacity));
+        }
+        chunks.last_mut().unwrap()
+    }
+
+    pub fn alloc(&self, value: T) -> &T {
+        let mut chunks = self.chunks.borrow_mut();
+        let chunk = Self::chunk_for(&mut chunks, 1);
+        chunk.push(value);
+        let value = std::ptr::from_ref(chunk.last().unwrap());
+        // SAFETY: the chunk had enough capacity, so its elements are never moved,
+        // and they're only dropped together with the arena
+        unsafe { &*value }
+    }
+
+    pub fn alloc_slice(&self, values: impl IntoIterator<Item = T>) -> &[T] {
+        let values = values.into_iter().collect::<Vec<_>>();
+        let mut chunks = self.chunks.borrow_mut();
+        let chunk = Self::chunk_for(&mut chunks, values.len());
+        let start = chunk.len();
+        chunk.extend(values);
+        let slice = std::ptr::from_ref(&chunk[start..]);
+        // SAFETY: same as in alloc
+        unsafe { &*slice }
+    }
+}
+
+/// Text written as a string or char literal between the quotes, escaping the chars
+/// the parser reads back from `\n`, `\t`, `\r`, `\0`, `\\`, the quote and `\u{...}` for
+/// other control chars. The compiled programs escape them the same way
+pub struct Quoted<'a>(pub &'a str, pub char);
+
+impl std::fmt::Display for Quoted<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self(text, quote) = *self;
+        write!(f, "{quote}")?;
+        for c in text.chars() {
+            match c {
+                '\n' => write!(f, "\\n")?,
+                '\t' => write!(f, "\\t")?,
+                '\r' => write!(f, "\\r")?,
+                '\0' => write!(f, "\\0")?,
+                '\\' => write!(f, "\\\\")?,
+                c if c == quote => write!(f, "\\{c}")?,
+                c if c.is_control() => write!(f, "\\u{{{:x}}}", u32::from(c))?,
+                c => write!(f, "{c}")?,
+            }
+        }
+        write!(f, "{quote}")
+    }
+}
@@ -1,11 +1,16 @@
 mod args;
 mod ast;
 mod common;
-use crate::args::get_args;
-use crate::ast::parser;
+use crate::args::{Emit, get_args};
+use crate::ast::{
+    Arenas, FinalLevel, InitialLevel, Options, Session, TraceOptions, parser, repl_parser,
+};
+use crate::common::Ident;
 use ariadne::{Color, Label, Report, ReportKind, sources};
 use chumsky::Parser;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::Path;
+use std::time::Duration;
 
 fn report_err(path: &str, text: &str, err: &chumsky::prelude::Rich<'_, char>) {
     Report::build(
@@ -29,39 +34,90 @@ fn report_err(path: &str, text: &str, err: &chumsky::prelude::Rich<'_, char>) {
     .unwrap();
 }
 
+fn lower(tree: InitialLevel<'_>, optimize: bool) -> FinalLevel<'_> {
+    let lowered = tree.lower_all_the_way();
+    if optimize {
+        lowered.optimize()
+    } else {
+        lowered
+    }
+}
+
 fn main() {
     let args = get_args();
+    let options = Options {
+        strategy: args.strategy,
+        normalize: args.normalize,
+        fuel: args.fuel,
+        timeout: args.timeout.map(Duration::from_secs_f64),
+        max_depth: Some(args.max_depth),
+    };
+    let arenas = Arenas::default();
     if let Some(path) = args.file {
         let text = std::fs::read_to_string(Path::new(&path)).unwrap();
-        let Ok(tree) = parser().parse(&text).into_result().map_err(|errs| {
+        let Ok(tree) = parser(&arenas).parse(&text).into_result().map_err(|errs| {
             for err in errs {
                 report_err(&path, &text, &err);
             }
         }) else {
-            return;
+            std::process::exit(1);
         };
+        if args.emit.is_some() || args.dump_ir {
+            let lowered = lower(tree, args.optimize);
+            if args.dump_ir {
+                print!("{lowered}");
+                return;
+            }
+            let Some(emit) = args.emit else { return };
+            match emit {
+                Emit::Wat => print!("{}", lowered.emit_wat()),
+                Emit::Asm => print!("{}", lowered.emit_asm()),
+            }
+            return;
+        }
         println!("{tree}");
-        let lowered = tree.lower_all_the_way();
-        lowered.eval();
+        let lowered = lower(tree, args.optimize);
+        let result = if args.trace {
+            let trace_options = TraceOptions {
+                depth: args.trace_depth,
+                skip_arithmetic: args.trace_skip_arithmetic,
+                only_in: args.trace_proc.as_deref().map(Ident::new),
+            };
+            lowered.trace(trace_options, options)
+        } else {
+            lowered.eval(options)
+        };
+        if let Err(err) = result {
+            eprintln!("error: {err}");
+            std::process::exit(1);
+        }
         return;
     }
-    unimplemented!("the REPL is not implemented yet")
-    // let mut rl = rustyline::DefaultEditor::new()?;
-    // loop {
-    //     let Ok(line) = rl.readline(">> ") else {
-    //         return Ok(());
-    //     };
-    //     rl.add_history_entry(&line)?;
-    //
-    //     let Ok(tree) = parser().parse(&line).into_result().map_err(|errs| {
-    //         for err in errs {
-    //             report_err("stdin", &line, &err);
-    //         }
-    //     }) else {
-    //         continue;
-    //     };
-    //
-    //     let lowered = tree.lower_all_the_way();
-    //     lowered.eval();
-    // }
+    // a line that panics is reported and the session goes on
+    panic::set_hook(Box::new(|info| match info.payload_as_str() {
+        Some(message) => eprintln!("error: {message}"),
+        None => eprintln!("error: {info}"),
+    }));
+    let mut session = Session::new(&arenas);
+    let mut rl = rustyline::DefaultEditor::new().unwrap();
+    while let Ok(line) = rl.readline(">> ") {
+        let _ = rl.add_history_entry(&line);
+
+        let Ok(line) = repl_parser(&arenas)
+            .parse(&line)
+            .into_result()
+            .map_err(|errs| {
+                for err in errs {
+                    report_err("stdin", &line, &err);
+                }
+            })
+        else {
+            continue;
+        };
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| session.run(line, options)));
+        if let Ok(Err(err)) = result {
+            eprintln!("error: {err}");
+        }
+    }
 }
@@ -0,0 +1,175 @@
+//! Every way of running a program gives the same result
+mod common;
+
+fn sample(name: &str) -> String {
+    std::fs::read_to_string(format!("tests/programs/{name}.tlang")).unwrap()
+}
+
+#[test]
+fn recursion() {
+    assert_eq!(
+        common::run_everywhere("recursion", &sample("recursion")),
+        "112"
+    );
+}
+
+#[test]
+fn lists() {
+    assert_eq!(common::run_everywhere("lists", &sample("lists")), "20");
+}
+
+#[test]
+fn tuples_and_closures() {
+    let source = "
+proc main() {
+    let (x, y) = (3, 4) in
+    let scale = k -> v -> v * k in
+    (scale x y) - 5
+}";
+    assert_eq!(common::run_everywhere("tuples_and_closures", source), "7");
+}
+
+#[test]
+fn main_returns_strings_and_chars() {
+    let source = r#"
+proc main() {
+    ("a\"b\\c\n\u{1}é€😀", '\'', '"', "it's")
+}"#;
+    assert_eq!(
+        common::run_everywhere("main_returns_strings_and_chars", source),
+        r#"("a\"b\\c\n\u{1}é€😀", '\'', '"', "it's")"#
+    );
+}
+
+#[test]
+fn main_returns_lists_and_records() {
+    let source = r#"
+record Point { x: i32, name: str }
+
+proc main() {
+    let p = {x = 1, name = "p"} in
+    ([p, {p with x = 2}], map!([1, 2], x -> x == 1), [].push!('c'))
+}"#;
+    assert_eq!(
+        common::run_everywhere("main_returns_lists_and_records", source),
+        r#"([{x = 1, name = "p"}, {x = 2, name = "p"}], [true, false], ['c'])"#
+    );
+}
+
+#[test]
+fn main_returning_a_function_isnt_compiled() {
+    let stderr = common::fail(
+        "main_returning_a_function_isnt_compiled",
+        "proc main() { x -> x + 1 }",
+        &["--emit", "asm"],
+    );
+    assert!(stderr.contains("main returns a function"), "{stderr}");
+}
+
+#[test]
+fn declared_types_arent_trusted_when_compiled() {
+    let source = r#"
+proc id(x: i32) -> i32 { x }
+proc main() {
+    let f = x -> x in
+    (id!(5), id!("s"), id!([1, 2]), f 'c', fold!(["a", "b"], "", acc s -> acc ++ s))
+}"#;
+    assert_eq!(
+        common::run_everywhere("declared_types_arent_trusted_when_compiled", source),
+        r#"(5, "s", [1, 2], 'c', "ab")"#
+    );
+}
+
+#[test]
+fn what_cant_be_checked_isnt_compiled() {
+    let name = "what_cant_be_checked_isnt_compiled";
+    let cases = [
+        (
+            "proc main() { \"ab\" == \"ab\" }",
+            "`==` is applied to values it doesn't work on",
+        ),
+        (
+            "proc f(x: i32) -> i32 { x * 2 }\nproc main() { f!(\"a\") }",
+            "`*` is applied to values it doesn't work on",
+        ),
+        (
+            "proc main() { let f = x -> x == x in (f 1, f \"a\") }",
+            "`==` is applied to values it doesn't work on",
+        ),
+        (
+            "proc main() { let f = if true then x -> x else 5 in f \"a\" }",
+            "what main returns can't be inferred",
+        ),
+    ];
+    for (source, expected) in cases {
+        let stderr = common::fail(name, source, &["--emit", "asm"]);
+        assert!(stderr.contains(expected), "{source}:\n{stderr}");
+    }
+}
+
+#[test]
+fn optimizing_inlines_and_specializes() {
+    let name = "optimizing_inlines_and_specializes";
+    let source = "
+proc scale(x: i32, k: i32) -> i32 { x * k }
+proc main() { scale!(3, 4) }";
+    assert_eq!(common::run_everywhere(name, source), "12");
+    let dumped = common::emit(name, source, &["--dump-ir", "--optimize"]);
+    assert_eq!(dumped.trim(), "proc main() -> () {\n    12\n}", "{dumped}");
+    // too big to inline, so it's copied for each lambda it's called with
+    let source = "
+proc times(f: i32, n: i32) -> i32 {
+    let rec go = k acc -> if k == 0 then acc else go (k - 1) (f acc) in
+    go n 1
+}
+proc main() { (times!(x -> x * 2, 10), times!(x -> x * 2, 5), times!(x -> x + 1, 3)) }";
+    assert_eq!(common::run_everywhere(name, source), "(1024, 32, 4)");
+    let dumped = common::emit(name, source, &["--dump-ir", "--optimize"]);
+    assert!(!dumped.contains("times!("), "{dumped}");
+    // the lambdas are applied in the copies
+    assert_eq!(dumped.matches(" * 2)").count(), 2, "{dumped}");
+    assert_eq!(dumped.matches(" + 1)").count(), 1, "{dumped}");
+}
+
+#[test]
+fn operator_functions_are_hygienic() {
+    let source = "
+proc add(a: i32, b: i32) -> i32 { a + b }
+infixl 6 <+> = x y -> add x y
+proc main() {
+    let add = 100 in
+    (1 <+> 2, (<+> add) 3)
+}";
+    assert_eq!(
+        common::run_everywhere("operator_functions_are_hygienic", source),
+        "(3, 103)"
+    );
+}
+
+#[test]
+fn arithmetic_wraps_around() {
+    assert_eq!(
+        common::run_everywhere("arithmetic", &sample("arithmetic")),
+        "(-2147483648, -2147483648, -2147483648, 0, 0, -7)"
+    );
+}
+
+#[test]
+fn normal_forms_are_printed_like_the_readme() {
+    let name = "normal_forms_are_printed_like_the_readme";
+    let cases = [
+        ("let k = 3 * 4 in x -> x * k", "x -> x * 12"),
+        (
+            "x -> y -> (x - (y - 1), (x + 1) * y - 2, (x ++ y) ++ x, x ++ y ++ x)",
+            "x -> y -> (x - (y - 1), (x + 1) * y - 2, (x ++ y) ++ x, x ++ y ++ x)",
+        ),
+        ("f -> x -> (f x) + f (x + 1)", "f -> x -> (f x) + f x + 1"),
+    ];
+    for (body, expected) in cases {
+        let source = format!("proc main() {{ {body} }}");
+        for strategy in ["strict", "lazy"] {
+            let flags = ["--normalize", "--strategy", strategy];
+            assert_eq!(common::run(name, &source, &flags), expected, "{strategy}");
+        }
+    }
+}
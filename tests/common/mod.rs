@@ -0,0 +1,113 @@
+//! Runs the binary on programs the way a user would.
+//! Every program is written to a file named after its test, so a failure can be rerun by hand
+#![allow(dead_code)] // every test file uses a different part of it
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn theorylang() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_theorylang"));
+    command.env("RUST_BACKTRACE", "0");
+    command
+}
+
+fn path(name: &str, extension: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{name}.{extension}"))
+}
+
+fn output(name: &str, source: &str, flags: &[&str]) -> Output {
+    let file = path(name, "tlang");
+    std::fs::write(&file, source).unwrap();
+    theorylang().arg(&file).args(flags).output().unwrap()
+}
+
+fn text(bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes).unwrap()
+}
+
+/// What the program prints with the flags, which must succeed
+pub fn emit(name: &str, source: &str, flags: &[&str]) -> String {
+    let output = output(name, source, flags);
+    let stderr = text(output.stderr);
+    assert!(output.status.success(), "{name} failed:\n{stderr}");
+    text(output.stdout)
+}
+
+/// The result of running the program with the flags, the program itself is printed before it
+pub fn run(name: &str, source: &str, flags: &[&str]) -> String {
+    let stdout = emit(name, source, flags);
+    stdout.lines().last().unwrap_or_default().to_owned()
+}
+
+/// What the program prints to stderr with the flags, which must fail
+pub fn fail(name: &str, source: &str, flags: &[&str]) -> String {
+    let output = output(name, source, flags);
+    let stderr = text(output.stderr);
+    assert!(!output.status.success(), "{name} succeeded:\n{stderr}");
+    stderr
+}
+
+/// The result printed by the program compiled to x86-64
+pub fn run_asm(name: &str, source: &str) -> String {
+    let asm = path(name, "s");
+    std::fs::write(&asm, emit(name, source, &["--emit", "asm"])).unwrap();
+    let binary = path(name, "bin");
+    let status = Command::new("cc")
+        .arg("-o")
+        .arg(&binary)
+        .arg(&asm)
+        .status()
+        .unwrap();
+    assert!(status.success(), "{name} didn't assemble");
+    let output = Command::new(&binary).output().unwrap();
+    assert!(output.status.success(), "{name} failed natively");
+    text(output.stdout).trim_end().to_owned()
+}
+
+/// The result printed by every way of running the program, which must all agree
+pub fn run_everywhere(name: &str, source: &str) -> String {
+    let result = run(name, source, &[]);
+    for flags in [
+        &["--strategy", "lazy"][..],
+        &["--optimize"],
+        &["--optimize", "--strategy", "lazy"],
+        &["--trace"],
+    ] {
+        assert_eq!(run(name, source, flags), result, "{name} with {flags:?}");
+    }
+    assert_eq!(run_asm(name, source), result, "{name} compiled to x86-64");
+    result
+}
+
+/// What the REPL prints to stdout and stderr for the lines
+pub fn repl(lines: &str, flags: &[&str]) -> (String, String) {
+    let mut child = theorylang()
+        .args(flags)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // it may have exited without reading them, like when the flags are rejected
+    let _ = child.stdin.take().unwrap().write_all(lines.as_bytes());
+    let output = child.wait_with_output().unwrap();
+    (text(output.stdout), text(output.stderr))
+}
+
+/// Checks the emitted text against the file in tests/golden,
+/// which is written instead when UPDATE_GOLDEN is set
+pub fn golden(file: &str, emitted: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(file);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, emitted).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_default();
+    assert!(
+        expected == emitted,
+        "{file} changed, rerun with UPDATE_GOLDEN=1 if that's intended:\n{emitted}"
+    );
+}
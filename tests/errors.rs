@@ -0,0 +1,265 @@
+//! Programs that are rejected, or stop with an error instead of a result
+mod common;
+
+#[test]
+fn field_shared_between_records() {
+    let source = "
+record A { x: i32 }
+proc mk() { {x = 1} }
+record B { y: i32, x: i32 }
+proc main() { mk!().x }";
+    let stderr = common::fail("field_shared_between_records", source, &[]);
+    assert!(
+        stderr.contains("the field x of B is already declared by A"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn record_declared_twice() {
+    let source = "
+record A { x: i32, y: i32 }
+record A { y: i32, x: i32 }
+proc main() { 1 }";
+    let stderr = common::fail("record_declared_twice", source, &[]);
+    assert!(
+        stderr.contains("the record A is already declared"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn field_of_a_tuple() {
+    let source = "
+record A { x: i32 }
+proc main() { (1, 2).x }";
+    for flags in [&[][..], &["--strategy", "lazy"]] {
+        let stderr = common::fail("field_of_a_tuple", source, flags);
+        assert!(
+            stderr.contains("error: a value had no field x"),
+            "{flags:?}:\n{stderr}"
+        );
+    }
+}
+
+#[test]
+fn infinite_normal_form_is_limited() {
+    let source = "
+proc main() {
+    let rec loop = n -> if n == 0 then 0 else loop (n - 1) in
+    x -> loop x
+}";
+    let name = "infinite_normal_form_is_limited";
+    for strategy in ["strict", "lazy"] {
+        let flags = ["--normalize", "--strategy", strategy, "--max-depth", "2000"];
+        let stderr = common::fail(name, source, &flags);
+        assert!(stderr.contains("error: too many nested calls"), "{stderr}");
+        let flags = ["--normalize", "--strategy", strategy, "--fuel", "5000"];
+        let stderr = common::fail(name, source, &flags);
+        assert!(stderr.contains("error: ran out of fuel"), "{stderr}");
+    }
+}
+
+#[test]
+fn tracing_stops_with_the_same_errors() {
+    let cases = [
+        ("proc main() { 1 / (2 - 2) }", "error: divided by zero"),
+        (
+            "proc main() { [1, 2].[5] }",
+            "error: indexed past the end of a list",
+        ),
+        (
+            "proc main() { let (a, 1) = (1, 2) in a }",
+            "error: a value didn't match its pattern",
+        ),
+    ];
+    for (source, expected) in cases {
+        let stderr = common::fail("tracing_stops_with_the_same_errors", source, &["--trace"]);
+        assert!(stderr.contains(expected), "{source}:\n{stderr}");
+    }
+}
+
+#[test]
+fn tracing_is_limited() {
+    let source = "proc main() { let rec f = n -> n + (f n) in f 1 }";
+    let name = "tracing_is_limited";
+    let stderr = common::fail(name, source, &["--trace", "--max-depth", "50"]);
+    assert!(stderr.contains("error: too many nested calls"), "{stderr}");
+    let stderr = common::fail(name, source, &["--trace", "--fuel", "100"]);
+    assert!(stderr.contains("error: ran out of fuel"), "{stderr}");
+    let source = "proc main() { let rec f = n -> f n in f 1 }";
+    let stderr = common::fail(name, source, &["--trace", "--timeout", "0.5"]);
+    assert!(stderr.contains("error: ran out of time"), "{stderr}");
+}
+
+#[test]
+fn operators_on_the_wrong_values() {
+    let cases = [
+        (
+            "proc main() { \"ab\" == \"ab\" }",
+            "error: `==` can't be applied",
+        ),
+        ("proc main() { 'a' + 'b' }", "error: `+` can't be applied"),
+        ("proc main() { 1 ++ 2 }", "error: `++` can't be applied"),
+        (
+            "proc main() { true < false }",
+            "error: `<` can't be applied",
+        ),
+    ];
+    for (source, expected) in cases {
+        for flags in [&[][..], &["--strategy", "lazy"], &["--trace"]] {
+            let stderr = common::fail("operators_on_the_wrong_values", source, flags);
+            assert!(stderr.contains(expected), "{source} {flags:?}:\n{stderr}");
+        }
+    }
+}
+
+#[test]
+fn field_of_another_record_when_optimized() {
+    let name = "field_of_another_record_when_optimized";
+    let records = "record A { x: i32 }\nrecord B { p: i32, q: i32 }\n";
+    for field in ["p", "q"] {
+        let source = format!("{records}proc main() {{ {{x = 1}}.{field} }}");
+        for flags in [&[][..], &["--optimize"]] {
+            let stderr = common::fail(name, &source, flags);
+            assert!(
+                stderr.contains(&format!("error: a value had no field {field}")),
+                "{field} {flags:?}:\n{stderr}"
+            );
+        }
+    }
+}
+
+#[test]
+fn optimizing_keeps_errors() {
+    let cases = [
+        (
+            "proc main() { let rec loop = n -> loop n in let x = 1 / 0 in (loop 1, x) }",
+            "error: divided by zero",
+        ),
+        (
+            "proc main() { let x = \"a\" * 1 in 5 }",
+            "error: `*` can't be applied",
+        ),
+        (
+            "proc f(x: i32) -> i32 { x * 1 }\nproc main() { f!(\"a\") }",
+            "error: `*` can't be applied",
+        ),
+        (
+            "proc f(x: i32) -> i32 { x + 0 }\nproc main() { f!(\"a\") }",
+            "error: `+` can't be applied",
+        ),
+    ];
+    for (source, expected) in cases {
+        for flags in [
+            &["--fuel", "100000"][..],
+            &["--fuel", "100000", "--optimize"],
+        ] {
+            let stderr = common::fail("optimizing_keeps_errors", source, flags);
+            assert!(stderr.contains(expected), "{source} {flags:?}:\n{stderr}");
+        }
+    }
+}
+
+#[test]
+fn calling_a_non_function() {
+    let source = "proc main() { 5 3 }";
+    for flags in [
+        &[][..],
+        &["--strategy", "lazy"],
+        &["--trace"],
+        &["--optimize"],
+    ] {
+        let stderr = common::fail("calling_a_non_function", source, flags);
+        assert!(
+            stderr.contains("error: a value that isn't a function was called"),
+            "{flags:?}:\n{stderr}"
+        );
+    }
+}
+
+#[test]
+fn pattern_of_another_shape() {
+    let bindings = [
+        "let (a, b) = (1, 2, 3) in a",
+        "let (a, b) = 5 in a",
+        "let 1 = true in 2",
+        "let true = (1, 2) in 2",
+    ];
+    for binding in bindings {
+        let source = format!("proc main() {{ {binding} }}");
+        for flags in [
+            &[][..],
+            &["--strategy", "lazy"],
+            &["--trace"],
+            &["--optimize"],
+        ] {
+            let stderr = common::fail("pattern_of_another_shape", &source, flags);
+            assert!(
+                stderr.contains("error: a value didn't match its pattern"),
+                "{binding} {flags:?}:\n{stderr}"
+            );
+        }
+    }
+}
+
+#[test]
+fn condition_that_isnt_a_bool() {
+    let source = "proc main() { if 1 then 2 else 3 }";
+    for flags in [
+        &[][..],
+        &["--strategy", "lazy"],
+        &["--trace"],
+        &["--optimize"],
+    ] {
+        let stderr = common::fail("condition_that_isnt_a_bool", source, flags);
+        assert!(
+            stderr.contains("error: the condition of an if wasn't a bool"),
+            "{flags:?}:\n{stderr}"
+        );
+    }
+}
+
+#[test]
+fn builtins_on_the_wrong_values() {
+    let cases = [
+        ("len!(5)", "error: `len!` can't be called with these values"),
+        (
+            "map!(3, x -> x)",
+            "error: `map!` can't be called with these values",
+        ),
+        (
+            "fold!(true, 0, (+))",
+            "error: `fold!` can't be called with these values",
+        ),
+        (
+            "push!(1, 2)",
+            "error: `push!` can't be called with these values",
+        ),
+        (
+            "[1].[\"a\"]",
+            "error: something that isn't a list was indexed",
+        ),
+    ];
+    for (call, expected) in cases {
+        let source = format!("proc main() {{ {call} }}");
+        for flags in [
+            &[][..],
+            &["--strategy", "lazy"],
+            &["--trace"],
+            &["--optimize"],
+        ] {
+            let stderr = common::fail("builtins_on_the_wrong_values", &source, flags);
+            assert!(stderr.contains(expected), "{call} {flags:?}:\n{stderr}");
+        }
+    }
+    // an unused binding is still evaluated when strict, optimized or not
+    let source = "proc main() { let _ = len!(5) in 1 }";
+    for flags in [&[][..], &["--trace"], &["--optimize"]] {
+        let stderr = common::fail("builtins_on_the_wrong_values", source, flags);
+        assert!(
+            stderr.contains("error: `len!` can't be called"),
+            "{flags:?}:\n{stderr}"
+        );
+    }
+}
@@ -0,0 +1,25 @@
+//! The emitted code of the sample programs in tests/programs, compared with tests/golden
+mod common;
+
+fn check(name: &str) {
+    let source = std::fs::read_to_string(format!("tests/programs/{name}.tlang")).unwrap();
+    for (backend, extension) in [("wat", "wat"), ("asm", "s")] {
+        let emitted = common::emit(name, &source, &["--emit", backend]);
+        common::golden(&format!("{name}.{extension}"), &emitted);
+    }
+}
+
+#[test]
+fn recursion() {
+    check("recursion");
+}
+
+#[test]
+fn lists() {
+    check("lists");
+}
+
+#[test]
+fn arithmetic() {
+    check("arithmetic");
+}
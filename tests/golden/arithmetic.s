@@ -0,0 +1,493 @@
+    .text
+rt_alloc:
+    movq rt_heap_ptr(%rip), %rax
+    addq %rax, %rdi
+    leaq rt_heap_end(%rip), %rcx
+    cmpq %rcx, %rdi
+    ja rt_out_of_memory
+    movq %rdi, rt_heap_ptr(%rip)
+    ret
+rt_concat:
+    movq %rdi, %r8
+    movq %rsi, %r9
+    movq (%r8), %rdx
+    addq (%r9), %rdx
+    leaq 8(,%rdx,8), %rdi
+    call rt_alloc
+    movq %rdx, (%rax)
+    leaq 8(%rax), %rdi
+    leaq 8(%r8), %rsi
+    movq (%r8), %rcx
+    rep movsq
+    leaq 8(%r9), %rsi
+    movq (%r9), %rcx
+    rep movsq
+    ret
+rt_index:
+    cmpq (%rdi), %rsi
+    jae rt_out_of_bounds
+    movq 8(%rdi,%rsi,8), %rax
+    ret
+rt_map:
+    pushq %rbx
+    pushq %r12
+    pushq %r13
+    pushq %r14
+    pushq %r15
+    movq %rdi, %r12
+    movq %rsi, %r13
+    movq (%r12), %rdi
+    leaq 8(,%rdi,8), %rdi
+    call rt_alloc
+    movq %rax, %r14
+    movq (%r12), %rcx
+    movq %rcx, (%r14)
+    xorl %ebx, %ebx
+rt_map_loop:
+    cmpq (%r12), %rbx
+    jae rt_map_end
+    movq %r13, %rdi
+    movq 8(%r12,%rbx,8), %rsi
+    call *(%rdi)
+    movq %rax, 8(%r14,%rbx,8)
+    incq %rbx
+    jmp rt_map_loop
+rt_map_end:
+    movq %r14, %rax
+    popq %r15
+    popq %r14
+    popq %r13
+    popq %r12
+    popq %rbx
+    ret
+rt_fold:
+    pushq %rbx
+    pushq %r12
+    pushq %r13
+    pushq %r14
+    pushq %r15
+    movq %rdi, %r12
+    movq %rsi, %r14
+    movq %rdx, %r13
+    xorl %ebx, %ebx
+rt_fold_loop:
+    cmpq (%r12), %rbx
+    jae rt_fold_end
+    movq %r13, %rdi
+    movq %r14, %rsi
+    call *(%rdi)
+    movq %rax, %rdi
+    movq 8(%r12,%rbx,8), %rsi
+    call *(%rdi)
+    movq %rax, %r14
+    incq %rbx
+    jmp rt_fold_loop
+rt_fold_end:
+    movq %r14, %rax
+    popq %r15
+    popq %r14
+    popq %r13
+    popq %r12
+    popq %rbx
+    ret
+rt_push:
+    movq %rdi, %r8
+    movq %rsi, %r9
+    movq (%r8), %rdx
+    leaq 16(,%rdx,8), %rdi
+    call rt_alloc
+    leaq 1(%rdx), %rcx
+    movq %rcx, (%rax)
+    leaq 8(%rax), %rdi
+    leaq 8(%r8), %rsi
+    movq %rdx, %rcx
+    rep movsq
+    movq %r9, (%rdi)
+    ret
+rt_out_of_bounds:
+    call abort@PLT
+rt_out_of_memory:
+    call abort@PLT
+rt_division_by_zero:
+    call abort@PLT
+rt_no_match:
+    call abort@PLT
+rt_print_number:
+    subq $8, %rsp
+    movl %edi, %esi
+    leaq rt_number_format(%rip), %rdi
+    xorl %eax, %eax
+    call printf@PLT
+    addq $8, %rsp
+    ret
+rt_print_text:
+    subq $8, %rsp
+    movq %rdi, %rsi
+    leaq rt_text_format(%rip), %rdi
+    xorl %eax, %eax
+    call printf@PLT
+    addq $8, %rsp
+    ret
+rt_print_separator:
+    leaq rt_separator(%rip), %rdi
+    jmp rt_print_text
+rt_print_bool:
+    leaq rt_true(%rip), %rax
+    leaq rt_false(%rip), %rcx
+    testq %rdi, %rdi
+    cmovzq %rcx, %rax
+    movq %rax, %rdi
+    jmp rt_print_text
+rt_put_char:
+    pushq %rbx
+    movl %edi, %ebx
+    cmpl $0x80, %ebx
+    jae rt_put_char_2
+    call putchar@PLT
+    popq %rbx
+    ret
+rt_put_char_2:
+    cmpl $0x800, %ebx
+    jae rt_put_char_3
+    shrl $6, %edi
+    orl $0xc0, %edi
+    call putchar@PLT
+    jmp rt_put_char_last
+rt_put_char_3:
+    cmpl $0x10000, %ebx
+    jae rt_put_char_4
+    shrl $12, %edi
+    orl $0xe0, %edi
+    call putchar@PLT
+    jmp rt_put_char_second_last
+rt_put_char_4:
+    shrl $18, %edi
+    orl $0xf0, %edi
+    call putchar@PLT
+    movl %ebx, %edi
+    shrl $12, %edi
+    andl $0x3f, %edi
+    orl $0x80, %edi
+    call putchar@PLT
+rt_put_char_second_last:
+    movl %ebx, %edi
+    shrl $6, %edi
+    andl $0x3f, %edi
+    orl $0x80, %edi
+    call putchar@PLT
+rt_put_char_last:
+    movl %ebx, %edi
+    andl $0x3f, %edi
+    orl $0x80, %edi
+    call putchar@PLT
+    popq %rbx
+    ret
+rt_put_escaped:
+    pushq %rbx
+    pushq %r12
+    subq $8, %rsp
+    movl %edi, %ebx
+    movl %edi, %r12d
+    cmpl %esi, %edi
+    je rt_put_escaped_letter
+    cmpl $92, %edi
+    je rt_put_escaped_letter
+    movl $110, %r12d
+    cmpl $10, %edi
+    je rt_put_escaped_letter
+    movl $116, %r12d
+    cmpl $9, %edi
+    je rt_put_escaped_letter
+    movl $114, %r12d
+    cmpl $13, %edi
+    je rt_put_escaped_letter
+    movl $48, %r12d
+    testl %edi, %edi
+    je rt_put_escaped_letter
+    cmpl $32, %edi
+    jb rt_put_escaped_code
+    cmpl $127, %edi
+    jb rt_put_escaped_plain
+    cmpl $159, %edi
+    jbe rt_put_escaped_code
+rt_put_escaped_plain:
+    call rt_put_char
+    jmp rt_put_escaped_end
+rt_put_escaped_letter:
+    movl $92, %edi
+    call putchar@PLT
+    movl %r12d, %edi
+    call putchar@PLT
+    jmp rt_put_escaped_end
+rt_put_escaped_code:
+    movl %ebx, %esi
+    leaq rt_code_format(%rip), %rdi
+    xorl %eax, %eax
+    call printf@PLT
+rt_put_escaped_end:
+    addq $8, %rsp
+    popq %r12
+    popq %rbx
+    ret
+rt_print_char:
+    pushq %rbx
+    movl %edi, %ebx
+    movl $39, %edi
+    call putchar@PLT
+    movl %ebx, %edi
+    movl $39, %esi
+    call rt_put_escaped
+    movl $39, %edi
+    call putchar@PLT
+    popq %rbx
+    ret
+rt_print_str:
+    pushq %rbx
+    pushq %r12
+    subq $8, %rsp
+    movq %rdi, %r12
+    movl $34, %edi
+    call putchar@PLT
+    xorl %ebx, %ebx
+rt_print_str_loop:
+    cmpq (%r12), %rbx
+    jae rt_print_str_end
+    movq 8(%r12,%rbx,8), %rdi
+    movl $34, %esi
+    call rt_put_escaped
+    incq %rbx
+    jmp rt_print_str_loop
+rt_print_str_end:
+    movl $34, %edi
+    call putchar@PLT
+    addq $8, %rsp
+    popq %r12
+    popq %rbx
+    ret
+
+    .globl main
+main:
+    pushq %rbp
+    movq %rsp, %rbp
+    leaq rt_heap(%rip), %rax
+    movq %rax, rt_heap_ptr(%rip)
+    call tl_main
+    movq %rax, %rdi
+    call rt_print_0
+    movl $10, %edi
+    call putchar@PLT
+    xorl %eax, %eax
+    popq %rbp
+    ret
+tl_main:
+    pushq %rbp
+    movq %rsp, %rbp
+    subq $16, %rsp
+    movq $8, %rdi
+    call rt_alloc
+    pushq %rax
+    movq (%rsp), %rcx
+    leaq tl_lambda_0(%rip), %rax
+    movq %rax, (%rcx)
+    popq %rax
+    pushq %rax
+    movq $0, %rax
+    pushq %rax
+    movq $2147483647, %rax
+    movq %rax, %rcx
+    popq %rax
+    subl %ecx, %eax
+    movslq %eax, %rax
+    pushq %rax
+    movq $1, %rax
+    movq %rax, %rcx
+    popq %rax
+    subl %ecx, %eax
+    movslq %eax, %rax
+    movq %rax, %rsi
+    popq %rdi
+    call *(%rdi)
+    leave
+    ret
+tl_lambda_0:
+    pushq %rbp
+    movq %rsp, %rbp
+    subq $16, %rsp
+    movq %rdi, -8(%rbp)
+    movq %rsi, -16(%rbp)
+    movq $16, %rdi
+    call rt_alloc
+    pushq %rax
+    movq -16(%rbp), %rax
+    movq (%rsp), %rcx
+    movq %rax, 8(%rcx)
+    movq (%rsp), %rcx
+    leaq tl_lambda_1(%rip), %rax
+    movq %rax, (%rcx)
+    popq %rax
+    pushq %rax
+    movq $0, %rax
+    pushq %rax
+    movq $1, %rax
+    movq %rax, %rcx
+    popq %rax
+    subl %ecx, %eax
+    movslq %eax, %rax
+    movq %rax, %rsi
+    popq %rdi
+    call *(%rdi)
+    leave
+    ret
+tl_lambda_1:
+    pushq %rbp
+    movq %rsp, %rbp
+    subq $16, %rsp
+    movq %rdi, -8(%rbp)
+    movq %rsi, -16(%rbp)
+    movq $48, %rdi
+    call rt_alloc
+    pushq %rax
+    movq $2147483647, %rax
+    pushq %rax
+    movq $1, %rax
+    movq %rax, %rcx
+    popq %rax
+    addl %ecx, %eax
+    movslq %eax, %rax
+    movq (%rsp), %rcx
+    movq %rax, 0(%rcx)
+    movq $0, %rax
+    pushq %rax
+    movq -8(%rbp), %rax
+    movq 8(%rax), %rax
+    movq %rax, %rcx
+    popq %rax
+    subl %ecx, %eax
+    movslq %eax, %rax
+    movq (%rsp), %rcx
+    movq %rax, 8(%rcx)
+    movq -8(%rbp), %rax
+    movq 8(%rax), %rax
+    pushq %rax
+    movq -16(%rbp), %rax
+    movq %rax, %rcx
+    popq %rax
+    testl %ecx, %ecx
+    je rt_division_by_zero
+    cmpl $-1, %ecx
+    jne .L0
+    negl %eax
+    xorl %edx, %edx
+    jmp .L1
+    .L0:
+    cltd
+    idivl %ecx
+    .L1:
+    movslq %eax, %rax
+    movq (%rsp), %rcx
+    movq %rax, 16(%rcx)
+    movq -8(%rbp), %rax
+    movq 8(%rax), %rax
+    pushq %rax
+    movq -16(%rbp), %rax
+    movq %rax, %rcx
+    popq %rax
+    testl %ecx, %ecx
+    je rt_division_by_zero
+    cmpl $-1, %ecx
+    jne .L2
+    negl %eax
+    xorl %edx, %edx
+    jmp .L3
+    .L2:
+    cltd
+    idivl %ecx
+    .L3:
+    movslq %edx, %rax
+    movq (%rsp), %rcx
+    movq %rax, 24(%rcx)
+    movq $65536, %rax
+    pushq %rax
+    movq $65536, %rax
+    movq %rax, %rcx
+    popq %rax
+    imull %ecx, %eax
+    movslq %eax, %rax
+    movq (%rsp), %rcx
+    movq %rax, 32(%rcx)
+    movq $7, %rax
+    pushq %rax
+    movq -16(%rbp), %rax
+    movq %rax, %rcx
+    popq %rax
+    testl %ecx, %ecx
+    je rt_division_by_zero
+    cmpl $-1, %ecx
+    jne .L4
+    negl %eax
+    xorl %edx, %edx
+    jmp .L5
+    .L4:
+    cltd
+    idivl %ecx
+    .L5:
+    movslq %eax, %rax
+    movq (%rsp), %rcx
+    movq %rax, 40(%rcx)
+    popq %rax
+    leave
+    ret
+rt_print_0:
+    pushq %rbx
+    pushq %r12
+    subq $8, %rsp
+    movq %rdi, %r12
+    movl $40, %edi
+    call putchar@PLT
+    movq 0(%r12), %rdi
+    call rt_print_number
+    call rt_print_separator
+    movq 8(%r12), %rdi
+    call rt_print_number
+    call rt_print_separator
+    movq 16(%r12), %rdi
+    call rt_print_number
+    call rt_print_separator
+    movq 24(%r12), %rdi
+    call rt_print_number
+    call rt_print_separator
+    movq 32(%r12), %rdi
+    call rt_print_number
+    call rt_print_separator
+    movq 40(%r12), %rdi
+    call rt_print_number
+    movl $41, %edi
+    call putchar@PLT
+    addq $8, %rsp
+    popq %r12
+    popq %rbx
+    ret
+
+    .section .rodata
+rt_number_format:
+    .string "%d"
+rt_text_format:
+    .string "%s"
+rt_code_format:
+    .string "\\u{%x}"
+rt_separator:
+    .string ", "
+rt_true:
+    .string "true"
+rt_false:
+    .string "false"
+
+    .bss
+    .p2align 4
+rt_heap_ptr:
+    .zero 8
+rt_heap:
+    .zero 67108864
+rt_heap_end:
+
+    .section .note.GNU-stack,"",@progbits
@@ -0,0 +1,313 @@
+(module
+  (type $closure (func (param i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (global $rt/heap (mut i32) (i32.const 0))
+  (func $rt/alloc (param $size i32) (result i32)
+    (local $ptr i32)
+    global.get $rt/heap
+    local.set $ptr
+    global.get $rt/heap
+    local.get $size
+    i32.add
+    global.set $rt/heap
+    block
+      global.get $rt/heap
+      memory.size
+      i32.const 16
+      i32.shl
+      i32.le_u
+      br_if 0
+      global.get $rt/heap
+      memory.size
+      i32.const 16
+      i32.shl
+      i32.sub
+      i32.const 16
+      i32.shr_u
+      i32.const 1
+      i32.add
+      memory.grow
+      drop
+    end
+    local.get $ptr)
+  (func $rt/div (param $a i32) (param $b i32) (result i32)
+    local.get $b
+    i32.const -1
+    i32.eq
+    if (result i32)
+      i32.const 0
+      local.get $a
+      i32.sub
+    else
+      local.get $a
+      local.get $b
+      i32.div_s
+    end)
+  (func $rt/concat (param $a i32) (param $b i32) (result i32)
+    (local $len i32)
+    (local $ptr i32)
+    local.get $a
+    i32.load
+    local.get $b
+    i32.load
+    i32.add
+    local.set $len
+    local.get $len
+    i32.const 1
+    i32.add
+    i32.const 2
+    i32.shl
+    call $rt/alloc
+    local.set $ptr
+    local.get $ptr
+    local.get $len
+    i32.store
+    local.get $ptr
+    i32.const 4
+    i32.add
+    local.get $a
+    i32.const 4
+    i32.add
+    local.get $a
+    i32.load
+    i32.const 2
+    i32.shl
+    memory.copy
+    local.get $ptr
+    i32.const 4
+    i32.add
+    local.get $a
+    i32.load
+    i32.const 2
+    i32.shl
+    i32.add
+    local.get $b
+    i32.const 4
+    i32.add
+    local.get $b
+    i32.load
+    i32.const 2
+    i32.shl
+    memory.copy
+    local.get $ptr)
+  (func $rt/index (param $list i32) (param $idx i32) (result i32)
+    local.get $idx
+    local.get $list
+    i32.load
+    i32.ge_u
+    if
+      unreachable
+    end
+    local.get $list
+    local.get $idx
+    i32.const 2
+    i32.shl
+    i32.add
+    i32.load offset=4)
+  (func $rt/map (param $list i32) (param $f i32) (result i32)
+    (local $len i32)
+    (local $new i32)
+    (local $idx i32)
+    local.get $list
+    i32.load
+    local.set $len
+    local.get $len
+    i32.const 1
+    i32.add
+    i32.const 2
+    i32.shl
+    call $rt/alloc
+    local.tee $new
+    local.get $len
+    i32.store
+    block
+      loop
+        local.get $idx
+        local.get $len
+        i32.ge_u
+        br_if 1
+        local.get $new
+        local.get $idx
+        i32.const 2
+        i32.shl
+        i32.add
+        local.get $f
+        local.get $list
+        local.get $idx
+        i32.const 2
+        i32.shl
+        i32.add
+        i32.load offset=4
+        local.get $f
+        i32.load
+        call_indirect (type $closure)
+        i32.store offset=4
+        local.get $idx
+        i32.const 1
+        i32.add
+        local.set $idx
+        br 0
+      end
+    end
+    local.get $new)
+  (func $rt/fold (param $list i32) (param $acc i32) (param $f i32) (result i32)
+    (local $idx i32)
+    (local $partial i32)
+    block
+      loop
+        local.get $idx
+        local.get $list
+        i32.load
+        i32.ge_u
+        br_if 1
+        local.get $f
+        local.get $acc
+        local.get $f
+        i32.load
+        call_indirect (type $closure)
+        local.tee $partial
+        local.get $list
+        local.get $idx
+        i32.const 2
+        i32.shl
+        i32.add
+        i32.load offset=4
+        local.get $partial
+        i32.load
+        call_indirect (type $closure)
+        local.set $acc
+        local.get $idx
+        i32.const 1
+        i32.add
+        local.set $idx
+        br 0
+      end
+    end
+    local.get $acc)
+  (func $rt/push (param $list i32) (param $new i32) (result i32)
+    (local $len i32)
+    (local $ptr i32)
+    local.get $list
+    i32.load
+    local.set $len
+    local.get $len
+    i32.const 2
+    i32.add
+    i32.const 2
+    i32.shl
+    call $rt/alloc
+    local.tee $ptr
+    local.get $len
+    i32.const 1
+    i32.add
+    i32.store
+    local.get $ptr
+    i32.const 4
+    i32.add
+    local.get $list
+    i32.const 4
+    i32.add
+    local.get $len
+    i32.const 2
+    i32.shl
+    memory.copy
+    local.get $ptr
+    local.get $len
+    i32.const 2
+    i32.shl
+    i32.add
+    local.get $new
+    i32.store offset=4
+    local.get $ptr)
+  (table 5 funcref)
+  (elem (i32.const 0) $lambda/0 $lambda/1 $lambda/2 $lambda/3 $lambda/4)
+  (func $product (param $#1 i32) (result i32)
+    (local $tmp/0 i32)
+    local.get $#1
+    i32.const 1
+    i32.const 4
+    call $rt/alloc
+    local.set $tmp/0
+    local.get $tmp/0
+    i32.const 0
+    i32.store
+    local.get $tmp/0
+    call $rt/fold)
+  (func $main (result i32)
+    (local $tmp/0 i32)
+    (local $tmp/1 i32)
+    (local $tmp/2 i32)
+    i32.const 4
+    call $rt/alloc
+    local.set $tmp/1
+    local.get $tmp/1
+    i32.const 2
+    i32.store
+    local.get $tmp/1
+    local.tee $tmp/0
+    i32.const 4
+    call $rt/alloc
+    local.tee $tmp/2
+    i32.const 0
+    i32.store
+    local.get $tmp/2
+    i32.const 3
+    call $rt/push
+    i32.const 4
+    call $rt/push
+    local.get $tmp/0
+    i32.load
+    call_indirect (type $closure))
+  (func $lambda/0 (param $env i32) (param $#2 i32) (result i32)
+    (local $tmp/0 i32)
+    i32.const 8
+    call $rt/alloc
+    local.set $tmp/0
+    local.get $tmp/0
+    local.get $#2
+    i32.store offset=4
+    local.get $tmp/0
+    i32.const 1
+    i32.store
+    local.get $tmp/0)
+  (func $lambda/1 (param $env i32) (param $#3 i32) (result i32)
+    local.get $env
+    i32.load offset=4
+    local.get $#3
+    i32.mul)
+  (func $lambda/2 (param $env i32) (param $#4 i32) (result i32)
+    (local $tmp/0 i32)
+    (local $tmp/1 i32)
+    local.get $#4
+    i32.const 4
+    call $rt/alloc
+    local.set $tmp/1
+    local.get $tmp/1
+    i32.const 3
+    i32.store
+    local.get $tmp/1
+    local.tee $tmp/0
+    i32.const 1
+    local.get $tmp/0
+    i32.load
+    call_indirect (type $closure)
+    call $rt/map
+    call $product)
+  (func $lambda/3 (param $env i32) (param $#5 i32) (result i32)
+    (local $tmp/0 i32)
+    i32.const 8
+    call $rt/alloc
+    local.set $tmp/0
+    local.get $tmp/0
+    local.get $#5
+    i32.store offset=4
+    local.get $tmp/0
+    i32.const 4
+    i32.store
+    local.get $tmp/0)
+  (func $lambda/4 (param $env i32) (param $#6 i32) (result i32)
+    local.get $#6
+    local.get $env
+    i32.load offset=4
+    i32.add)
+  (export "main" (func $main)))
@@ -0,0 +1,56 @@
+//! Lines given to the REPL on stdin
+mod common;
+
+#[test]
+fn errors_dont_end_the_session() {
+    let lines = "\
+nope
+proc f(x: i32) -> i32 { x + 1 }
+proc g() -> i32 { nope }
+f!(2)
+1 / 0
+if 1 then 2 else 3
+f!(3)
+";
+    let (stdout, stderr) = common::repl(lines, &[]);
+    assert_eq!(stdout, "3\n4\n", "{stderr}");
+    assert_eq!(
+        stderr
+            .matches("error: that binding (nope) wasn't found")
+            .count(),
+        2,
+        "{stderr}"
+    );
+    assert!(stderr.contains("error: divided by zero"), "{stderr}");
+    assert!(
+        stderr.contains("error: the condition of an if wasn't a bool"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn failed_definitions_are_forgotten() {
+    let lines = "\
+proc f() -> i32 { 1 }
+proc g() -> i32 { 2 } proc h() -> i32 { nope }
+g!()
+f!()
+";
+    let (stdout, stderr) = common::repl(lines, &[]);
+    assert_eq!(stdout, "1\n", "{stderr}");
+    assert!(
+        stderr.contains("error: that proc (g) wasn't found"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn file_only_flags_are_rejected() {
+    for flag in ["--optimize", "--trace", "--dump-ir", "--emit=wat"] {
+        let (_, stderr) = common::repl("1 + 1\n", &[flag]);
+        assert!(
+            stderr.contains("required arguments were not provided"),
+            "{flag}:\n{stderr}"
+        );
+    }
+}
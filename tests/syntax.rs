@@ -0,0 +1,83 @@
+//! How source text is read
+mod common;
+
+#[test]
+fn doc_comments_elsewhere_are_comments() {
+    let source = "
+/// a record
+record A { x: i32 }
+/// an operator
+infixl 6 <+> = (x -> y -> x + y)
+
+/// the answer
+// not documentation
+/// really
+proc main() {
+    /// inside a body
+    let a = {x = 1} in
+    a.x <+> 41 /// after an expression
+}
+/// at the end";
+    let name = "doc_comments_elsewhere_are_comments";
+    let printed = common::emit(name, source, &[]);
+    assert!(
+        printed.starts_with("record A { x: i32 }\ninfixl 6 <+> = x -> y -> x + y\n/// the answer\n/// really\nproc main()"),
+        "{printed}"
+    );
+    assert_eq!(common::run(name, source, &[]), "42");
+}
+
+#[test]
+fn parse_errors_fail() {
+    let stderr = common::fail("parse_errors_fail", "proc main() { (1, }", &[]);
+    assert!(stderr.contains("Error"), "{stderr}");
+}
+
+#[test]
+fn number_literals() {
+    let source = "proc main() { (0x_7fff_ffff, -0x8000_0000, 0o17, 0b1_01, 1_000, 10i32) }";
+    let name = "number_literals";
+    assert_eq!(
+        common::run(name, source, &[]),
+        "(2147483647, -2147483648, 15, 5, 1000, 10)"
+    );
+    // the suffix is kept in the tree
+    let source = "proc main() { let 1i32 = 1 in -0b1_1i32 }";
+    let printed = common::emit(name, source, &[]);
+    assert!(printed.contains("let 1i32 = 1 in\n    -3i32"), "{printed}");
+    let cases = [
+        ("300i8", "`i8` isn't the type of a number"),
+        ("10bool", "`bool` isn't the type of a number"),
+        ("0o19", "`9` isn't a digit in octal"),
+        ("0x8000_0000", "`0x8000_0000` doesn't fit in i32"),
+        ("0x8000_0000i32", "`0x8000_0000i32` doesn't fit in i32"),
+        ("0b_", "expected binary digits"),
+    ];
+    for (literal, expected) in cases {
+        let source = format!("proc main() {{ {literal} }}");
+        let stderr = common::fail(name, &source, &[]);
+        assert!(stderr.contains(expected), "{literal}:\n{stderr}");
+    }
+}
+
+#[test]
+fn lambdas_of_several_arguments() {
+    let source = "
+proc main() {
+    let f = (x, y) -> x * y in
+    let g = ((a, b)) c -> a + b + c in
+    let h = x - y <- (x, y) in
+    (f 3 4, (f 2) 5, g (1, 2) 3, h 1 2)
+}";
+    let name = "lambdas_of_several_arguments";
+    assert_eq!(common::run(name, source, &[]), "(12, 10, 6, -1)");
+    // the printed program reads the same
+    let printed = common::emit(name, source, &[]);
+    assert!(printed.contains("let f = x y -> x * y in"), "{printed}");
+    assert!(
+        printed.contains("let g = ((a, b)) c -> a + b + c in"),
+        "{printed}"
+    );
+    let (program, _) = printed.trim_end().rsplit_once('\n').unwrap();
+    assert_eq!(common::run(name, program, &[]), "(12, 10, 6, -1)");
+}